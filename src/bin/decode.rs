@@ -1,20 +1,60 @@
-use std::io::{stdin, stdout, BufRead, BufWriter, Result, Write};
+use std::env;
+use std::fs;
+use std::io::{stdin, stdout, BufWriter, Error, ErrorKind, Result, Write};
 
 use lz::{
-    decoder::Decoder,
-    token::{DecoderAdapter, Token},
+    decode_with_vocab,
+    token::Vocab,
+    token_file::{TokenFile, TokenFormat},
 };
 
+/// parse `--format text|bin|json`, `--dictionary FILE` and `--window-bits N`, the window
+/// of text input, the other formats record it
+fn parse_args() -> Result<(TokenFormat, Vec<u8>, Vocab)> {
+    let mut format = TokenFormat::Text;
+    let mut vocab = Vocab::default();
+    let mut dictionary = Vec::new();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let invalid = || {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid argument: {}", arg),
+            )
+        };
+        match arg.as_str() {
+            "--format" => {
+                format = args
+                    .next()
+                    .as_deref()
+                    .and_then(TokenFormat::parse)
+                    .ok_or_else(invalid)?;
+            }
+            "--dictionary" => dictionary = fs::read(args.next().ok_or_else(invalid)?)?,
+            "--window-bits" => {
+                vocab = args
+                    .next()
+                    .and_then(|n| n.parse().ok())
+                    .and_then(Vocab::new)
+                    .ok_or_else(invalid)?;
+            }
+            _ => return Err(invalid()),
+        }
+    }
+    Ok((format, dictionary, vocab))
+}
+
+/// writes the documents one after another
 fn main() -> Result<()> {
-    let reader = stdin().lock();
+    let (format, dictionary, vocab) = parse_args()?;
+    let file = TokenFile::read(stdin().lock(), format, vocab)?;
+    // validated by read
+    let vocab = file.vocab().unwrap();
     let mut writer = BufWriter::new(stdout());
-    let iter = reader
-        .lines()
-        .map(|line| Token::from(line.unwrap().parse::<u16>().unwrap()));
-    let adapter = DecoderAdapter::new(iter);
-    let decoder = Decoder::new(adapter);
-    for x in decoder {
-        writer.write_all(&[x])?;
+    for document in file.documents() {
+        let xs = decode_with_vocab(document, vocab, &dictionary)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        writer.write_all(&xs)?;
     }
-    Ok(())
+    writer.flush()
 }
@@ -0,0 +1,57 @@
+use std::env;
+use std::io::{stdin, stdout, BufWriter, Error, ErrorKind, Result, Write};
+
+use lz::{
+    deflate::{compress, Format},
+    encoder::{EncoderConfig, Parsing},
+};
+
+/// parse `-0` ~ `-9` or `--level N`, gzip style, `--optimal` and `--format raw|zlib|gzip`
+fn parse_args() -> Result<(EncoderConfig, Format)> {
+    let mut config = EncoderConfig::default();
+    let mut parsing = Parsing::Lazy;
+    let mut format = Format::Gzip;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let invalid = || {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid argument: {}", arg),
+            )
+        };
+        if arg == "--optimal" {
+            parsing = Parsing::Optimal;
+            continue;
+        }
+        if arg == "--format" {
+            format = match args.next().as_deref() {
+                Some("raw") => Format::Raw,
+                Some("zlib") => Format::Zlib,
+                Some("gzip") => Format::Gzip,
+                _ => return Err(invalid()),
+            };
+            continue;
+        }
+        let level = match arg.as_str() {
+            "--level" => args.next().unwrap_or_default(),
+            _ => match arg.strip_prefix('-') {
+                Some(level) => level.to_string(),
+                None => String::new(),
+            },
+        };
+        config = level
+            .parse()
+            .ok()
+            .and_then(EncoderConfig::level)
+            .ok_or_else(invalid)?;
+    }
+    config.parsing = parsing;
+    Ok((config, format))
+}
+
+fn main() -> Result<()> {
+    let (config, format) = parse_args()?;
+    let writer = BufWriter::new(stdout());
+    let mut writer = compress(stdin(), writer, config, format)?;
+    writer.flush()
+}
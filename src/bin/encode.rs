@@ -1,13 +1,189 @@
-use std::io::{stdin, stdout, BufWriter, Result, Write};
+use std::env;
+use std::fs::{self, File};
+use std::io::{stdin, stdout, BufWriter, Error, ErrorKind, Read, Result};
 
-use lz::{encoder::Encoder, token::EncoderAdaptor};
+use lz::{
+    encode_parallel,
+    encoder::{Encoder, EncoderConfig, Parsing},
+    hash::{HashFunction, MAX_HASH_BITS, MAX_HASH_LEN, MIN_HASH_BITS, MIN_HASH_LEN},
+    lz77::{DEFAULT_WINDOW_BITS, MAX_LENGTH, MIN_LENGTH},
+    match_finder::MatchFinderKind,
+    token::{EncoderAdaptor, Vocab},
+    token_file::{TokenFile, TokenFormat},
+};
 
-fn main() -> Result<()> {
-    let mut writer = BufWriter::new(stdout());
-    let encoder = Encoder::new(stdin(), false);
-    let adaptor = EncoderAdaptor::new(encoder);
-    for token in adaptor {
-        writer.write_all(format!("{}", u16::from(token)).as_bytes())?;
+struct Args {
+    config: EncoderConfig,
+    format: TokenFormat,
+    dictionary: Vec<u8>,
+    /// encode each input in blocks of this size on all cores, see encode_parallel
+    block_size: Option<usize>,
+    paths: Vec<String>,
+}
+
+/// parse `-0` ~ `-9` or `--level N`, gzip style, `--optimal`, `--window-bits N`,
+/// `--hash shift-xor|multiplicative`, `--hash-len N`, `--hash-bits N`, `--min-match N`,
+/// `--match-finder hc|bt`, `--format text|bin|json`, `--dictionary FILE`, `--block-size N`
+/// and the input files, each encoded as a document
+fn parse_args() -> Result<Args> {
+    let mut config = EncoderConfig::default();
+    let mut parsing = Parsing::Lazy;
+    let mut window_bits = DEFAULT_WINDOW_BITS;
+    let defaults = EncoderConfig::default();
+    let mut hash_function = defaults.hash_function;
+    let mut hash_len = defaults.hash_len;
+    let mut hash_bits = defaults.hash_bits;
+    let mut min_match = defaults.min_match;
+    let mut match_finder = defaults.match_finder;
+    let mut format = TokenFormat::Text;
+    let mut dictionary = Vec::new();
+    let mut block_size = None;
+    let mut paths = Vec::new();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let invalid = || {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid argument: {}", arg),
+            )
+        };
+        if arg == "--optimal" {
+            parsing = Parsing::Optimal;
+            continue;
+        }
+        if arg == "--window-bits" {
+            window_bits = args
+                .next()
+                .and_then(|n| n.parse().ok())
+                .filter(|&n| Vocab::new(n).is_some())
+                .ok_or_else(invalid)?;
+            continue;
+        }
+        if arg == "--hash" {
+            hash_function = args
+                .next()
+                .as_deref()
+                .and_then(HashFunction::parse)
+                .ok_or_else(invalid)?;
+            continue;
+        }
+        if arg == "--hash-len" {
+            hash_len = args
+                .next()
+                .and_then(|n| n.parse().ok())
+                .filter(|n| (MIN_HASH_LEN..=MAX_HASH_LEN).contains(n))
+                .ok_or_else(invalid)?;
+            continue;
+        }
+        if arg == "--hash-bits" {
+            hash_bits = args
+                .next()
+                .and_then(|n| n.parse().ok())
+                .filter(|n| (MIN_HASH_BITS..=MAX_HASH_BITS).contains(n))
+                .ok_or_else(invalid)?;
+            continue;
+        }
+        if arg == "--min-match" {
+            min_match = args
+                .next()
+                .and_then(|n| n.parse().ok())
+                .filter(|n| (MIN_LENGTH..=MAX_LENGTH).contains(n))
+                .ok_or_else(invalid)?;
+            continue;
+        }
+        if arg == "--match-finder" {
+            match_finder = args
+                .next()
+                .as_deref()
+                .and_then(MatchFinderKind::parse)
+                .ok_or_else(invalid)?;
+            continue;
+        }
+        if arg == "--format" {
+            format = args
+                .next()
+                .as_deref()
+                .and_then(TokenFormat::parse)
+                .ok_or_else(invalid)?;
+            continue;
+        }
+        if arg == "--dictionary" {
+            dictionary = fs::read(args.next().ok_or_else(invalid)?)?;
+            continue;
+        }
+        if arg == "--block-size" {
+            block_size = args
+                .next()
+                .and_then(|n| n.parse().ok())
+                .map(Some)
+                .ok_or_else(invalid)?;
+            continue;
+        }
+        if !arg.starts_with('-') {
+            paths.push(arg);
+            continue;
+        }
+        let level = match arg.as_str() {
+            "--level" => args.next().unwrap_or_default(),
+            _ => arg[1..].to_string(),
+        };
+        config = level
+            .parse()
+            .ok()
+            .and_then(EncoderConfig::level)
+            .ok_or_else(invalid)?;
     }
-    Ok(())
+    if block_size.is_some() && !dictionary.is_empty() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "--block-size and --dictionary are exclusive",
+        ));
+    }
+    config.parsing = parsing;
+    config.window_bits = window_bits;
+    config.hash_function = hash_function;
+    config.hash_len = hash_len;
+    config.hash_bits = hash_bits;
+    config.min_match = min_match;
+    config.match_finder = match_finder;
+    if block_size.is_some_and(|n| n < config.max_distance()) {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "--block-size must be at least the window, 2^--window-bits",
+        ));
+    }
+    Ok(Args {
+        config,
+        format,
+        dictionary,
+        block_size,
+        paths,
+    })
+}
+
+fn encode<R: Read>(mut read: R, args: &Args) -> Result<Vec<u16>> {
+    if let Some(block_size) = args.block_size {
+        let mut xs = Vec::new();
+        read.read_to_end(&mut xs)?;
+        return Ok(encode_parallel(&xs, args.config, block_size));
+    }
+    let encoder = Encoder::with_config(read, args.config, false).with_dictionary(&args.dictionary);
+    let vocab = args.config.vocab();
+    Ok(EncoderAdaptor::new(encoder).map(|x| vocab.id(x)).collect())
+}
+
+fn main() -> Result<()> {
+    let args = parse_args()?;
+    let file = if args.paths.is_empty() {
+        TokenFile::new(encode(stdin(), &args)?)
+    } else {
+        let documents = args
+            .paths
+            .iter()
+            .map(|path| encode(File::open(path)?, &args))
+            .collect::<Result<Vec<_>>>()?;
+        TokenFile::from_documents(&documents)
+    };
+    file.with_vocab(args.config.vocab())
+        .write(BufWriter::new(stdout()), args.format)
 }
@@ -0,0 +1,84 @@
+use std::env;
+use std::io::{Error, ErrorKind, Result};
+use std::path::PathBuf;
+
+use lz::{
+    encoder::{EncoderConfig, Parsing},
+    lz77::DEFAULT_WINDOW_BITS,
+    pack::{list_files, pack, DEFAULT_SHARD_SIZE},
+    token::Vocab,
+};
+
+struct Args {
+    config: EncoderConfig,
+    shard_size: usize,
+    output: PathBuf,
+    paths: Vec<PathBuf>,
+}
+
+/// parse `-0` ~ `-9` or `--level N`, gzip style, `--optimal`, `--window-bits N`,
+/// `--shard-size N`, `--output PREFIX` and the input files or directories
+fn parse_args() -> Result<Args> {
+    let mut config = EncoderConfig::default();
+    let mut parsing = Parsing::Lazy;
+    let mut window_bits = DEFAULT_WINDOW_BITS;
+    let mut shard_size = DEFAULT_SHARD_SIZE;
+    let mut output = PathBuf::from("shard");
+    let mut paths = Vec::new();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let invalid = || {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid argument: {}", arg),
+            )
+        };
+        match arg.as_str() {
+            "--optimal" => parsing = Parsing::Optimal,
+            "--window-bits" => {
+                window_bits = args
+                    .next()
+                    .and_then(|n| n.parse().ok())
+                    .filter(|&n| Vocab::new(n).is_some())
+                    .ok_or_else(invalid)?;
+            }
+            "--shard-size" => {
+                shard_size = args
+                    .next()
+                    .and_then(|n| n.parse().ok())
+                    .filter(|&n| n > 0)
+                    .ok_or_else(invalid)?;
+            }
+            "--output" => output = args.next().ok_or_else(invalid)?.into(),
+            _ if !arg.starts_with('-') => paths.extend(list_files(&arg)?),
+            _ => {
+                let level = match arg.as_str() {
+                    "--level" => args.next().unwrap_or_default(),
+                    _ => arg[1..].to_string(),
+                };
+                config = level
+                    .parse()
+                    .ok()
+                    .and_then(EncoderConfig::level)
+                    .ok_or_else(invalid)?;
+            }
+        }
+    }
+    config.parsing = parsing;
+    config.window_bits = window_bits;
+    Ok(Args {
+        config,
+        shard_size,
+        output,
+        paths,
+    })
+}
+
+fn main() -> Result<()> {
+    let args = parse_args()?;
+    let shards = pack(&args.paths, &args.output, args.shard_size, args.config)?;
+    for shard in shards {
+        println!("{}", shard.display());
+    }
+    Ok(())
+}
@@ -0,0 +1,90 @@
+use std::env;
+use std::fs;
+use std::io::{stdout, Error, ErrorKind, Result, Write};
+use std::path::PathBuf;
+
+use lz::{
+    dictionary::train_dictionary_with_config,
+    encoder::{EncoderConfig, Parsing},
+    lz77::DEFAULT_WINDOW_BITS,
+    pack::list_files,
+    token::Vocab,
+};
+
+const DEFAULT_SIZE: usize = 1 << 15; // 32k
+
+struct Args {
+    config: EncoderConfig,
+    size: usize,
+    paths: Vec<PathBuf>,
+}
+
+/// parse `-0` ~ `-9` or `--level N`, gzip style, `--optimal`, `--window-bits N`,
+/// `--size N` and the sample files or directories
+fn parse_args() -> Result<Args> {
+    let mut config = EncoderConfig::default();
+    let mut parsing = Parsing::Lazy;
+    let mut window_bits = DEFAULT_WINDOW_BITS;
+    let mut size = DEFAULT_SIZE;
+    let mut paths = Vec::new();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let invalid = || {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid argument: {}", arg),
+            )
+        };
+        match arg.as_str() {
+            "--optimal" => parsing = Parsing::Optimal,
+            "--window-bits" => {
+                window_bits = args
+                    .next()
+                    .and_then(|n| n.parse().ok())
+                    .filter(|&n| Vocab::new(n).is_some())
+                    .ok_or_else(invalid)?;
+            }
+            "--size" => {
+                size = args
+                    .next()
+                    .and_then(|n| n.parse().ok())
+                    .filter(|&n| n > 0)
+                    .ok_or_else(invalid)?;
+            }
+            _ if !arg.starts_with('-') => paths.extend(list_files(&arg)?),
+            _ => {
+                let level = match arg.as_str() {
+                    "--level" => args.next().unwrap_or_default(),
+                    _ => arg[1..].to_string(),
+                };
+                config = level
+                    .parse()
+                    .ok()
+                    .and_then(EncoderConfig::level)
+                    .ok_or_else(invalid)?;
+            }
+        }
+    }
+    config.parsing = parsing;
+    config.window_bits = window_bits;
+    Ok(Args {
+        config,
+        size,
+        paths,
+    })
+}
+
+/// writes the dictionary trained on the samples
+fn main() -> Result<()> {
+    let args = parse_args()?;
+    let samples = args
+        .paths
+        .iter()
+        .map(fs::read)
+        .collect::<Result<Vec<_>>>()?;
+    let samples: Vec<&[u8]> = samples.iter().map(Vec::as_slice).collect();
+    let dictionary = train_dictionary_with_config(&samples, args.size, args.config);
+    let mut writer = stdout();
+    writer.write_all(&dictionary)?;
+    writer.flush()
+}
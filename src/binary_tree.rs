@@ -0,0 +1,151 @@
+use crate::encoder::EncoderConfig;
+use crate::hash::RunningHasher;
+use crate::lz77::MAX_LENGTH;
+use crate::match_finder::{match_length, MatchFinder};
+
+/// no position, i.e., an empty subtree
+const NONE: usize = usize::MAX;
+
+/// LZMA's bt4: positions with the same hash of their first hash_len bytes form a binary
+/// search tree, ordered by the bytes that follow, with the most recent position at
+/// the root. Each insertion splits the tree around the new position on the way down,
+/// so a search visits candidates by increasing distance and skips those sharing a
+/// shorter prefix than the best so far, finding the longest matches where a hash
+/// chain would give up after max_count candidates
+pub struct BinaryTree {
+    /// a fresh hasher of config.hash_function, hash_len and hash_bits, copied for each
+    /// position, since positions that come up short of bytes are not hashed
+    hasher: RunningHasher,
+    hash_len: usize,
+    head: Vec<usize>,
+    /// smaller and larger child of each position, indexed by position in a cyclic
+    /// buffer of two windows so that a position a whole window back is still there
+    son: Vec<usize>,
+    cyclic_mask: usize,
+    max_distance: usize,
+    nice_length: usize,
+    max_chain: usize,
+}
+
+impl BinaryTree {
+    pub fn new(config: &EncoderConfig) -> Self {
+        let cyclic_len = 2 * config.max_distance();
+        Self {
+            hasher: RunningHasher::with_params(
+                config.hash_function,
+                config.hash_len,
+                config.hash_bits,
+            ),
+            hash_len: config.hash_len,
+            head: vec![NONE; 1 << config.hash_bits],
+            son: vec![NONE; 2 * cyclic_len],
+            cyclic_mask: cyclic_len - 1,
+            max_distance: config.max_distance(),
+            nice_length: config.nice_length,
+            max_chain: config.max_chain,
+        }
+    }
+
+    fn hash(&self, buf: &[u8], pos: usize) -> usize {
+        let buf_mask = buf.len() - 1;
+        let mut hasher = self.hasher.clone();
+        for i in 0..self.hash_len {
+            hasher.update(buf[(pos + i) & buf_mask]);
+        }
+        hasher.get()
+    }
+
+    /// walk down from the root of the hash of pos, reporting the improving matches,
+    /// and re-link the visited nodes as the subtrees of pos, the new root
+    fn update<F: FnMut(usize, usize)>(
+        &mut self,
+        buf: &[u8],
+        pos: usize,
+        cap: usize,
+        mut best_length: usize,
+        mut max_count: usize,
+        mut on_match: F,
+    ) -> (usize, usize) {
+        let buf_mask = buf.len() - 1;
+        let avail = MAX_LENGTH.min(cap - pos);
+        if avail < self.hash_len {
+            return (best_length, 0);
+        }
+        // the tree orders positions by their first len_limit bytes only
+        let len_limit = self.nice_length.max(self.hash_len).min(avail);
+
+        let h = self.hash(buf, pos);
+        let mut cur = self.head[h];
+        self.head[h] = pos;
+
+        let mut best_distance = 0;
+        // where to link the next smaller and larger node, and their common prefix with pos
+        let mut ptr1 = 2 * (pos & self.cyclic_mask);
+        let mut ptr0 = ptr1 + 1;
+        let (mut len1, mut len0) = (0, 0);
+        loop {
+            if cur == NONE || pos - cur > self.max_distance || max_count == 0 {
+                self.son[ptr0] = NONE;
+                self.son[ptr1] = NONE;
+                break;
+            }
+            max_count -= 1;
+            let distance = pos - cur;
+            let pair = 2 * (cur & self.cyclic_mask);
+            let min_len = len0.min(len1);
+            let len =
+                min_len + match_length(buf, pos + min_len, cur + min_len, len_limit - min_len);
+            // the tree stops comparing at len_limit, the match may go on
+            let length = if len == len_limit {
+                len + match_length(buf, pos + len, cur + len, avail - len)
+            } else {
+                len
+            };
+            if length > best_length {
+                best_length = length;
+                best_distance = distance;
+                on_match(length, distance);
+            }
+            if len == len_limit {
+                // cur is replaced by pos, which takes over its subtrees
+                self.son[ptr1] = self.son[pair];
+                self.son[ptr0] = self.son[pair + 1];
+                break;
+            }
+            if buf[(cur + len) & buf_mask] < buf[(pos + len) & buf_mask] {
+                self.son[ptr1] = cur;
+                ptr1 = pair + 1;
+                cur = self.son[ptr1];
+                len1 = len;
+            } else {
+                self.son[ptr0] = cur;
+                ptr0 = pair;
+                cur = self.son[ptr0];
+                len0 = len;
+            }
+        }
+        (best_length, best_distance)
+    }
+}
+
+impl MatchFinder for BinaryTree {
+    fn hash_len(&self) -> usize {
+        self.hash_len
+    }
+
+    fn skip(&mut self, buf: &[u8], pos: usize, cap: usize) {
+        self.update(buf, pos, cap, MAX_LENGTH, self.max_chain, |_, _| {});
+    }
+
+    fn find<F: FnMut(usize, usize)>(
+        &mut self,
+        buf: &[u8],
+        pos: usize,
+        cap: usize,
+        best_length: usize,
+        max_count: usize,
+        on_match: F,
+    ) -> (usize, usize) {
+        self.update(buf, pos, cap, best_length, max_count, on_match)
+    }
+}
@@ -0,0 +1,98 @@
+/// CRC-32 as used by gzip (RFC 1952)
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub struct Crc32 {
+    crc: u32,
+}
+
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 == 1 {
+                0xEDB88320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+    table
+};
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Crc32 {
+    pub fn new() -> Self {
+        Self { crc: 0 }
+    }
+
+    pub fn update(&mut self, xs: &[u8]) {
+        let mut c = !self.crc;
+        for &x in xs {
+            c = CRC_TABLE[((c ^ x as u32) & 0xFF) as usize] ^ (c >> 8);
+        }
+        self.crc = !c;
+    }
+
+    pub fn get(&self) -> u32 {
+        self.crc
+    }
+}
+
+/// Adler-32 as used by zlib (RFC 1950)
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub struct Adler32 {
+    a: u32,
+    b: u32,
+}
+
+impl Default for Adler32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Adler32 {
+    const MOD: u32 = 65521;
+    /// largest n such that 255 n (n + 1) / 2 + (n + 1) (MOD - 1) fits in u32
+    const NMAX: usize = 5552;
+
+    pub fn new() -> Self {
+        Self { a: 1, b: 0 }
+    }
+
+    pub fn update(&mut self, xs: &[u8]) {
+        for chunk in xs.chunks(Self::NMAX) {
+            for &x in chunk {
+                self.a += x as u32;
+                self.b += self.a;
+            }
+            self.a %= Self::MOD;
+            self.b %= Self::MOD;
+        }
+    }
+
+    pub fn get(&self) -> u32 {
+        self.b << 16 | self.a
+    }
+}
+
+#[test]
+fn test_checksum() {
+    let mut crc = Crc32::new();
+    crc.update(b"123456789");
+    assert_eq!(crc.get(), 0xCBF43926);
+
+    let mut adler = Adler32::new();
+    adler.update(b"Wikipedia");
+    assert_eq!(adler.get(), 0x11E60398);
+}
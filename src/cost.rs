@@ -0,0 +1,75 @@
+use crate::token::{Token, Vocab};
+
+/// Cost of emitting each Lz77 symbol, minimized by the optimal parser
+pub trait CostModel {
+    fn literal(&self, x: u8) -> f32;
+
+    /// length: 3~258, distance: 1~2^window_bits
+    fn dictionary(&self, length: usize, distance: usize) -> f32;
+}
+
+/// Number of tokens emitted by EncoderAdaptor
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub struct TokenCount;
+
+impl CostModel for TokenCount {
+    fn literal(&self, _x: u8) -> f32 {
+        1.0
+    }
+
+    fn dictionary(&self, _length: usize, _distance: usize) -> f32 {
+        3.0
+    }
+}
+
+/// Per-token weight, indexed by token id; a symbol costs the sum of its tokens.
+/// The vocabulary, and so the window, is the one with as many ids as weights.
+/// Weights are finite and non-negative, else the optimal parser finds no path
+#[derive(Clone, PartialEq, Debug)]
+pub struct TokenWeights {
+    weights: Vec<f32>,
+    vocab: Vocab,
+}
+
+impl TokenWeights {
+    /// None unless there is one weight per token id of some vocabulary, and all of
+    /// them are finite and non-negative
+    pub fn new(weights: Vec<f32>) -> Option<Self> {
+        let vocab = Vocab::from_size(weights.len())?;
+        if !weights.iter().all(|x| x.is_finite() && *x >= 0.0) {
+            return None;
+        }
+        Some(Self { weights, vocab })
+    }
+
+    /// estimated bits per token, -log2(p), from token frequencies with add-one smoothing
+    pub fn from_counts(counts: &[usize]) -> Self {
+        let total = (counts.iter().sum::<usize>() + counts.len()) as f32;
+        let weights = counts
+            .iter()
+            .map(|&count| (total / (count + 1) as f32).log2())
+            .collect();
+        Self::new(weights).expect("one count per token id")
+    }
+
+    pub fn vocab(&self) -> Vocab {
+        self.vocab
+    }
+
+    fn get(&self, token: Token) -> f32 {
+        self.weights[self.vocab.id(token) as usize]
+    }
+}
+
+impl CostModel for TokenWeights {
+    fn literal(&self, x: u8) -> f32 {
+        self.get(Token::Literal(x))
+    }
+
+    fn dictionary(&self, length: usize, distance: usize) -> f32 {
+        let d = distance - 1;
+        self.get(Token::Length((length - 3) as u8))
+            + self.get(Token::Distance0((d & 0xFF) as u8))
+            + self.get(Token::Distance1((d >> 8) as u16))
+    }
+}
@@ -1,56 +1,209 @@
-use crate::lz77::{Lz77, MAX_DISTANCE, MAX_LENGTH};
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
 
-const BUF_LEN: usize = 1 << 16;
-const BUF_MASK: usize = BUF_LEN - 1;
+use crate::lz77::{Lz77, MAX_DISTANCE, MAX_LENGTH, MAX_WINDOW_BITS, MIN_WINDOW_BITS};
+use crate::token::Token;
+
+/// the buffer holds two windows, and at least 64k
+const MIN_BUF_LEN: usize = 1 << 16;
+
+/// position: index of the offending token in the input
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum DecodeError {
+    /// id outside of the vocabulary
+    InvalidToken { position: usize, id: u16 },
+    /// token that is not allowed at this position, e.g., a stray Distance0
+    UnexpectedToken { position: usize, token: Token },
+    /// id that is not allowed at this position, for vocabularies other than Vocab
+    UnexpectedId { position: usize, id: u16 },
+    /// stream ends in the middle of a Length/Distance0/Distance1 triple
+    UnexpectedEnd { position: usize },
+    /// back-reference reaching before the first decoded byte, or beyond the window;
+    /// from Decoder itself, position counts Lz77 codes rather than tokens
+    InvalidDistance { position: usize, distance: usize },
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidToken { position, id } => {
+                write!(f, "invalid token id {} at position {}", id, position)
+            }
+            Self::UnexpectedToken { position, token } => {
+                write!(f, "unexpected token {:?} at position {}", token, position)
+            }
+            Self::UnexpectedId { position, id } => {
+                write!(f, "unexpected token id {} at position {}", id, position)
+            }
+            Self::UnexpectedEnd { position } => {
+                write!(f, "unexpected end of tokens at position {}", position)
+            }
+            Self::InvalidDistance { position, distance } => {
+                write!(f, "invalid distance {} at position {}", distance, position)
+            }
+        }
+    }
+}
+
+impl Error for DecodeError {}
+
+/// position: index of the repaired token in the input
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum Repair {
+    /// id outside of the vocabulary was dropped
+    InvalidToken { position: usize, id: u16 },
+    /// token that is not allowed at this position was dropped
+    Dropped { position: usize, token: Token },
+    /// Length/Distance0 without Distance1 was completed with Distance1(0)
+    MissingDistance1 { position: usize },
+    /// back-reference reaching before the first decoded byte was clamped to it
+    ClampedDistance { position: usize, distance: usize },
+}
+
+impl Repair {
+    pub fn position(&self) -> usize {
+        let mut repair = *self;
+        *repair.position_mut()
+    }
+
+    pub(crate) fn position_mut(&mut self) -> &mut usize {
+        match self {
+            Self::InvalidToken { position, .. }
+            | Self::Dropped { position, .. }
+            | Self::MissingDistance1 { position }
+            | Self::ClampedDistance { position, .. } => position,
+        }
+    }
+}
+
+impl Display for Repair {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidToken { position, id } => {
+                write!(
+                    f,
+                    "dropped invalid token id {} at position {}",
+                    id, position
+                )
+            }
+            Self::Dropped { position, token } => {
+                write!(
+                    f,
+                    "dropped unexpected token {:?} at position {}",
+                    token, position
+                )
+            }
+            Self::MissingDistance1 { position } => {
+                write!(f, "filled in missing Distance1 at position {}", position)
+            }
+            Self::ClampedDistance { position, distance } => {
+                write!(
+                    f,
+                    "clamped invalid distance {} at position {}",
+                    distance, position
+                )
+            }
+        }
+    }
+}
 
 pub struct Decoder<I> {
     iter: I,
     buf: Vec<u8>,
     pos: usize,
     cap: usize,
+    max_distance: usize,
+    codes: usize, // number of codes decoded so far
 }
 
 impl<I> Decoder<I> {
     pub fn new(iter: I) -> Self {
         Self {
             iter,
-            buf: vec![0; BUF_LEN],
+            buf: vec![0; MIN_BUF_LEN],
             pos: 0,
             cap: 0,
+            max_distance: MAX_DISTANCE,
+            codes: 0,
         }
     }
+
+    /// window of 2^window_bits bytes that the encoder used, see EncoderConfig
+    pub fn with_window_bits(mut self, window_bits: u32) -> Self {
+        assert!(self.cap == 0, "window must be set before decoding");
+        assert!(
+            (MIN_WINDOW_BITS..=MAX_WINDOW_BITS).contains(&window_bits),
+            "invalid window_bits"
+        );
+        self.max_distance = 1 << window_bits;
+        self.buf = vec![0; (2 * self.max_distance).max(MIN_BUF_LEN)];
+        self
+    }
+
+    /// pre-fill the window with the preset dictionary the encoder used; the adapter
+    /// needs to be told its length with DecoderAdapter::with_dictionary_len
+    pub fn with_dictionary(mut self, dictionary: &[u8]) -> Self {
+        assert!(self.cap == 0, "dictionary must be set before decoding");
+        let dictionary = &dictionary[dictionary.len().saturating_sub(self.max_distance)..];
+        self.buf[..dictionary.len()].copy_from_slice(dictionary);
+        self.cap = dictionary.len();
+        self.pos = dictionary.len();
+        self
+    }
+
+    pub fn get_ref(&self) -> &I {
+        &self.iter
+    }
+
+    pub fn get_mut(&mut self) -> &mut I {
+        &mut self.iter
+    }
 }
 
-impl<I: Iterator<Item = Lz77>> Iterator for Decoder<I> {
-    type Item = u8;
+impl<I: Iterator<Item = Result<Lz77, DecodeError>>> Iterator for Decoder<I> {
+    type Item = Result<u8, DecodeError>;
 
     fn next(&mut self) -> Option<Self::Item> {
         // slide back if necessary
-        if self.cap >= BUF_LEN - MAX_LENGTH {
-            let n = self.cap - MAX_DISTANCE;
-            self.buf.copy_within(self.cap - MAX_DISTANCE..self.cap, 0);
+        if self.cap >= self.buf.len() - MAX_LENGTH {
+            // keep the window, and the rest of a match longer than a small window
+            let n = (self.cap - self.max_distance).min(self.pos);
+            self.buf.copy_within(n..self.cap, 0);
             self.cap -= n;
             self.pos -= n;
         }
 
         if self.pos < self.cap {
-            let x = self.buf[self.pos & BUF_MASK];
+            let x = self.buf[self.pos];
             self.pos += 1;
-            return Some(x);
+            return Some(Ok(x));
         }
 
         // self.pos == self.cap
-        match self.iter.next()? {
+        let code = match self.iter.next()? {
+            Ok(code) => code,
+            Err(e) => return Some(Err(e)),
+        };
+        self.codes += 1;
+        match code {
             Lz77::Literal(x) => {
-                self.buf[self.cap & BUF_MASK] = x;
+                self.buf[self.cap] = x;
                 self.cap += 1;
                 self.pos += 1;
-                Some(x)
+                Some(Ok(x))
             }
             Lz77::Dictionary { length, distance } => {
                 let mut length = length as usize + 3;
                 let mut distance = distance as usize;
-                let mut idx = self.cap & BUF_MASK;
+                // the adapter checks distances too, unless told another window or
+                // dictionary length than this decoder
+                if distance == 0 || distance > self.cap || distance > self.max_distance {
+                    return Some(Err(DecodeError::InvalidDistance {
+                        position: self.codes - 1,
+                        distance,
+                    }));
+                }
+                let mut idx = self.cap;
                 self.cap += length;
                 let begin = idx - distance;
                 while length > 0 {
@@ -63,7 +216,7 @@ impl<I: Iterator<Item = Lz77>> Iterator for Decoder<I> {
 
                 let x = self.buf[self.pos];
                 self.pos += 1;
-                Some(x)
+                Some(Ok(x))
             }
         }
     }
@@ -0,0 +1,428 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::io::{Read, Result, Write};
+
+use crate::checksum::{Adler32, Crc32};
+use crate::encoder::{Encoder, EncoderConfig};
+use crate::lz77::{Lz77, DEFAULT_WINDOW_BITS};
+
+/// base length and number of extra bits of length codes 257~285
+pub(crate) const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+pub(crate) const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+/// base distance and number of extra bits of distance codes 0~29
+pub(crate) const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+pub(crate) const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+/// order in which the code length code lengths are stored
+pub(crate) const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+pub(crate) const END_OF_BLOCK: usize = 256;
+
+const NUM_LITLEN: usize = 286;
+const NUM_DIST: usize = 30;
+const NUM_CODE_LENGTH: usize = 19;
+const MAX_BITS: u8 = 15;
+const MAX_CODE_LENGTH_BITS: u8 = 7;
+const BLOCK_LEN: usize = 1 << 15; // number of Lz77 codes per block
+
+/// Framing around the DEFLATE bitstream
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum Format {
+    /// bare RFC 1951
+    Raw,
+    /// RFC 1950, with Adler-32
+    Zlib,
+    /// RFC 1952, with CRC-32 and size
+    Gzip,
+}
+
+/// LSB-first bit packing
+pub(crate) struct BitWriter<W> {
+    writer: W,
+    buf: u64,
+    n: u32,
+}
+
+impl<W: Write> BitWriter<W> {
+    pub(crate) fn new(writer: W) -> Self {
+        Self {
+            writer,
+            buf: 0,
+            n: 0,
+        }
+    }
+
+    /// n <= 32
+    pub(crate) fn write_bits(&mut self, x: u32, n: u32) -> Result<()> {
+        self.buf |= (x as u64) << self.n;
+        self.n += n;
+        if self.n >= 32 {
+            self.writer.write_all(&(self.buf as u32).to_le_bytes())?;
+            self.buf >>= 32;
+            self.n -= 32;
+        }
+        Ok(())
+    }
+
+    /// pad with zeros up to the byte boundary
+    pub(crate) fn finish(mut self) -> Result<W> {
+        let n = self.n.div_ceil(8) as usize;
+        self.writer.write_all(&self.buf.to_le_bytes()[..n])?;
+        Ok(self.writer)
+    }
+}
+
+/// Code lengths of a Huffman code over freqs, limited to max_bits; unused symbols get 0.
+/// at least two symbols get a code, so the code is always complete
+fn code_lengths(freqs: &[u32], max_bits: u8) -> Vec<u8> {
+    let mut lengths = vec![0; freqs.len()];
+    let mut symbols: Vec<usize> = (0..freqs.len()).filter(|&i| freqs[i] > 0).collect();
+    for i in 0.. {
+        if symbols.len() >= 2 {
+            break;
+        }
+        if !symbols.contains(&i) {
+            symbols.push(i);
+        }
+    }
+
+    // Huffman tree; nodes 0..n are the symbols, and parents[i] is the parent of node i
+    let n = symbols.len();
+    let mut parents = vec![0; 2 * n - 1];
+    let mut heap: BinaryHeap<_> = symbols
+        .iter()
+        .enumerate()
+        .map(|(i, &x)| Reverse((freqs[x] as u64, i)))
+        .collect();
+    for node in n..2 * n - 1 {
+        let Reverse((f1, i1)) = heap.pop().unwrap();
+        let Reverse((f2, i2)) = heap.pop().unwrap();
+        parents[i1] = node;
+        parents[i2] = node;
+        heap.push(Reverse((f1 + f2, node)));
+    }
+    let mut depths = vec![0usize; 2 * n - 1];
+    for node in (0..2 * n - 2).rev() {
+        depths[node] = depths[parents[node]] + 1;
+    }
+
+    // clamp to max_bits and restore the Kraft inequality, then hand out
+    // the lengths to the symbols in the order of decreasing frequency
+    let max_bits = max_bits as usize;
+    let mut counts = vec![0usize; max_bits + 1];
+    for &depth in &depths[..n] {
+        counts[depth.min(max_bits)] += 1;
+    }
+    let mut total: usize = (1..=max_bits).map(|i| counts[i] << (max_bits - i)).sum();
+    while total > 1 << max_bits {
+        counts[max_bits] -= 1;
+        for i in (1..max_bits).rev() {
+            if counts[i] > 0 {
+                counts[i] -= 1;
+                counts[i + 1] += 2;
+                break;
+            }
+        }
+        total -= 1;
+    }
+    symbols.sort_by_key(|&x| Reverse(freqs[x]));
+    let mut symbols = symbols.into_iter();
+    for (length, &count) in counts.iter().enumerate().skip(1) {
+        for x in symbols.by_ref().take(count) {
+            lengths[x] = length as u8;
+        }
+    }
+    lengths
+}
+
+/// Canonical codes from code lengths, bit-reversed for LSB-first packing
+pub(crate) fn canonical_codes(lengths: &[u8]) -> Vec<u16> {
+    let mut counts = [0u32; MAX_BITS as usize + 1];
+    for &length in lengths {
+        counts[length as usize] += 1;
+    }
+    counts[0] = 0;
+    let mut next = [0u32; MAX_BITS as usize + 2];
+    for bits in 1..=MAX_BITS as usize {
+        next[bits + 1] = (next[bits] + counts[bits]) << 1;
+    }
+    lengths
+        .iter()
+        .map(|&length| {
+            if length == 0 {
+                return 0;
+            }
+            let code = next[length as usize];
+            next[length as usize] += 1;
+            (code as u16).reverse_bits() >> (16 - length)
+        })
+        .collect()
+}
+
+pub(crate) fn fixed_lengths() -> (Vec<u8>, Vec<u8>) {
+    let mut litlen = vec![8; 288];
+    litlen[144..256].fill(9);
+    litlen[256..280].fill(7);
+    (litlen, vec![5; NUM_DIST])
+}
+
+/// (code index, extra bits value) of a length 3~258
+pub(crate) fn length_code(length: usize) -> (usize, u32) {
+    let i = LENGTH_BASE.partition_point(|&base| base as usize <= length) - 1;
+    (i, (length - LENGTH_BASE[i] as usize) as u32)
+}
+
+/// (code index, extra bits value) of a distance 1~32k
+fn dist_code(distance: usize) -> (usize, u32) {
+    let i = DIST_BASE.partition_point(|&base| base as usize <= distance) - 1;
+    (i, (distance - DIST_BASE[i] as usize) as u32)
+}
+
+/// code length sequence, run-length encoded with symbols 16, 17 and 18;
+/// returns (symbol, extra bits value)
+fn run_lengths(lengths: &[u8]) -> Vec<(u8, u32)> {
+    let mut result = Vec::new();
+    let mut i = 0;
+    while i < lengths.len() {
+        let x = lengths[i];
+        let run = lengths[i..].iter().take_while(|&&y| y == x).count();
+        if x == 0 && run >= 11 {
+            let n = run.min(138);
+            result.push((18, (n - 11) as u32));
+            i += n;
+        } else if x == 0 && run >= 3 {
+            result.push((17, (run - 3) as u32));
+            i += run;
+        } else if x != 0 && run >= 4 {
+            result.push((x, 0));
+            let n = (run - 1).min(6);
+            result.push((16, (n - 3) as u32));
+            i += n + 1;
+        } else {
+            result.push((x, 0));
+            i += 1;
+        }
+    }
+    result
+}
+
+fn extra_bits(symbol: u8) -> u32 {
+    match symbol {
+        16 => 2,
+        17 => 3,
+        18 => 7,
+        _ => 0,
+    }
+}
+
+/// Huffman stage that writes Lz77 codes as DEFLATE blocks; each block is
+/// written with either the fixed or its own dynamic code, whichever is smaller
+pub struct DeflateWriter<W> {
+    bits: BitWriter<W>,
+    codes: Vec<Lz77>,
+}
+
+impl<W: Write> DeflateWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            bits: BitWriter::new(writer),
+            codes: Vec::with_capacity(BLOCK_LEN),
+        }
+    }
+
+    pub fn write(&mut self, code: Lz77) -> Result<()> {
+        self.codes.push(code);
+        if self.codes.len() == BLOCK_LEN {
+            self.write_block(false)?;
+        }
+        Ok(())
+    }
+
+    /// write the final block and return the underlying writer
+    pub fn finish(mut self) -> Result<W> {
+        self.write_block(true)?;
+        self.bits.finish()
+    }
+
+    fn write_block(&mut self, last: bool) -> Result<()> {
+        let mut litlen_freqs = vec![0u32; NUM_LITLEN];
+        let mut dist_freqs = vec![0u32; NUM_DIST];
+        litlen_freqs[END_OF_BLOCK] = 1;
+        for &code in &self.codes {
+            match code {
+                Lz77::Literal(x) => litlen_freqs[x as usize] += 1,
+                Lz77::Dictionary { length, distance } => {
+                    litlen_freqs[257 + length_code(length as usize + 3).0] += 1;
+                    dist_freqs[dist_code(distance as usize).0] += 1;
+                }
+            }
+        }
+
+        let litlen = code_lengths(&litlen_freqs, MAX_BITS);
+        let dist = code_lengths(&dist_freqs, MAX_BITS);
+        let hlit = 257.max(litlen.iter().rposition(|&x| x > 0).unwrap() + 1);
+        let hdist = 1.max(dist.iter().rposition(|&x| x > 0).unwrap() + 1);
+        let mut lengths = litlen[..hlit].to_vec();
+        lengths.extend_from_slice(&dist[..hdist]);
+        let runs = run_lengths(&lengths);
+        let mut code_length_freqs = vec![0u32; NUM_CODE_LENGTH];
+        for &(symbol, _) in &runs {
+            code_length_freqs[symbol as usize] += 1;
+        }
+        let code_length = code_lengths(&code_length_freqs, MAX_CODE_LENGTH_BITS);
+        let hclen = 4.max(
+            CODE_LENGTH_ORDER
+                .iter()
+                .rposition(|&x| code_length[x] > 0)
+                .unwrap()
+                + 1,
+        );
+
+        let (fixed_litlen, fixed_dist) = fixed_lengths();
+        let header_size = 14
+            + 3 * hclen
+            + runs
+                .iter()
+                .map(|&(x, _)| (code_length[x as usize] as u32 + extra_bits(x)) as usize)
+                .sum::<usize>();
+        let dynamic_size = header_size + self.block_size(&litlen, &dist);
+        let fixed_size = self.block_size(&fixed_litlen, &fixed_dist);
+
+        self.bits.write_bits(last as u32, 1)?;
+        if fixed_size <= dynamic_size {
+            self.bits.write_bits(1, 2)?;
+            self.write_codes(&fixed_litlen, &fixed_dist)?;
+        } else {
+            self.bits.write_bits(2, 2)?;
+            self.bits.write_bits((hlit - 257) as u32, 5)?;
+            self.bits.write_bits((hdist - 1) as u32, 5)?;
+            self.bits.write_bits((hclen - 4) as u32, 4)?;
+            for &x in &CODE_LENGTH_ORDER[..hclen] {
+                self.bits.write_bits(code_length[x] as u32, 3)?;
+            }
+            let codes = canonical_codes(&code_length);
+            for &(x, extra) in &runs {
+                let x = x as usize;
+                self.bits
+                    .write_bits(codes[x] as u32, code_length[x] as u32)?;
+                self.bits.write_bits(extra, extra_bits(x as u8))?;
+            }
+            self.write_codes(&litlen, &dist)?;
+        }
+        self.codes.clear();
+        Ok(())
+    }
+
+    /// number of bits for the codes of the block, excluding the header
+    fn block_size(&self, litlen: &[u8], dist: &[u8]) -> usize {
+        let mut size = litlen[END_OF_BLOCK] as usize;
+        for &code in &self.codes {
+            size += match code {
+                Lz77::Literal(x) => litlen[x as usize] as usize,
+                Lz77::Dictionary { length, distance } => {
+                    let (i, _) = length_code(length as usize + 3);
+                    let (j, _) = dist_code(distance as usize);
+                    (litlen[257 + i] + LENGTH_EXTRA[i] + dist[j] + DIST_EXTRA[j]) as usize
+                }
+            };
+        }
+        size + 3
+    }
+
+    fn write_codes(&mut self, litlen: &[u8], dist: &[u8]) -> Result<()> {
+        let litlen_codes = canonical_codes(litlen);
+        let dist_codes = canonical_codes(dist);
+        for &code in &self.codes {
+            match code {
+                Lz77::Literal(x) => {
+                    let x = x as usize;
+                    self.bits
+                        .write_bits(litlen_codes[x] as u32, litlen[x] as u32)?;
+                }
+                Lz77::Dictionary { length, distance } => {
+                    let (i, extra) = length_code(length as usize + 3);
+                    let x = 257 + i;
+                    self.bits
+                        .write_bits(litlen_codes[x] as u32, litlen[x] as u32)?;
+                    self.bits.write_bits(extra, LENGTH_EXTRA[i] as u32)?;
+                    let (j, extra) = dist_code(distance as usize);
+                    self.bits.write_bits(dist_codes[j] as u32, dist[j] as u32)?;
+                    self.bits.write_bits(extra, DIST_EXTRA[j] as u32)?;
+                }
+            }
+        }
+        let x = END_OF_BLOCK;
+        self.bits
+            .write_bits(litlen_codes[x] as u32, litlen[x] as u32)
+    }
+}
+
+/// keeps the checksums of everything read through it
+struct ChecksumReader<R> {
+    read: R,
+    crc: Crc32,
+    adler: Adler32,
+    len: usize,
+}
+
+impl<R: Read> Read for ChecksumReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let n = self.read.read(buf)?;
+        self.crc.update(&buf[..n]);
+        self.adler.update(&buf[..n]);
+        self.len += n;
+        Ok(n)
+    }
+}
+
+/// compress everything from read into writer and return the writer;
+/// the window is capped to the 32k of DEFLATE
+pub fn compress<R: Read, W: Write>(
+    read: R,
+    mut writer: W,
+    mut config: EncoderConfig,
+    format: Format,
+) -> Result<W> {
+    config.window_bits = config.window_bits.min(DEFAULT_WINDOW_BITS);
+    match format {
+        Format::Raw => {}
+        Format::Zlib => writer.write_all(&[0x78, 0x9C])?,
+        // no file name, no modification time, unknown OS
+        Format::Gzip => writer.write_all(&[0x1F, 0x8B, 8, 0, 0, 0, 0, 0, 0, 0xFF])?,
+    }
+
+    let read = ChecksumReader {
+        read,
+        crc: Crc32::new(),
+        adler: Adler32::new(),
+        len: 0,
+    };
+    let mut encoder = Encoder::with_config(read, config, false);
+    let mut deflate = DeflateWriter::new(writer);
+    for code in encoder.by_ref() {
+        deflate.write(code)?;
+    }
+    let mut writer = deflate.finish()?;
+
+    let read = encoder.get_mut();
+    match format {
+        Format::Raw => {}
+        Format::Zlib => writer.write_all(&read.adler.get().to_be_bytes())?,
+        Format::Gzip => {
+            writer.write_all(&read.crc.get().to_le_bytes())?;
+            writer.write_all(&(read.len as u32).to_le_bytes())?;
+        }
+    }
+    Ok(writer)
+}
@@ -0,0 +1,134 @@
+use std::collections::{HashMap, HashSet};
+
+use rayon::prelude::*;
+
+use crate::encoder::{Encoder, EncoderConfig};
+use crate::token::EncoderAdaptor;
+
+/// segments are scored by the d-grams they contain
+const DMER_LEN: usize = 8;
+/// candidate segment lengths, the best one is picked by the resulting token count
+const SEGMENT_LENS: [usize; 5] = [16, 32, 64, 128, 256];
+
+fn dmer(xs: &[u8], i: usize) -> u64 {
+    u64::from_le_bytes(xs[i..i + DMER_LEN].try_into().unwrap())
+}
+
+fn dmers(xs: &[u8]) -> impl Iterator<Item = u64> + '_ {
+    (0..(xs.len() + 1).saturating_sub(DMER_LEN)).map(|i| dmer(xs, i))
+}
+
+/// number of tokens for all samples when encoded with the dictionary
+pub fn token_count(samples: &[&[u8]], dictionary: &[u8], config: EncoderConfig) -> usize {
+    samples
+        .par_iter()
+        .map(|sample| {
+            let encoder = Encoder::with_config(*sample, config, false).with_dictionary(dictionary);
+            EncoderAdaptor::new(encoder).count()
+        })
+        .sum()
+}
+
+/// Picks the segments of the samples that are most useful as a preset dictionary of at
+/// most size bytes (capped to the window), like zstd's --train; the result minimizes
+/// the token count of the samples among the candidate segment lengths
+pub fn train_dictionary(samples: &[&[u8]], size: usize) -> Vec<u8> {
+    train_dictionary_with_config(samples, size, EncoderConfig::default())
+}
+
+pub fn train_dictionary_with_config(
+    samples: &[&[u8]],
+    size: usize,
+    config: EncoderConfig,
+) -> Vec<u8> {
+    let size = size.min(config.max_distance());
+    if size == 0 {
+        return Vec::new();
+    }
+    SEGMENT_LENS
+        .iter()
+        .filter(|&&k| k <= size.max(SEGMENT_LENS[0]))
+        .map(|&k| cover(samples, size, k))
+        .map(|dictionary| (token_count(samples, &dictionary, config), dictionary))
+        .min_by_key(|(count, dictionary)| (*count, dictionary.len()))
+        .map(|(_, dictionary)| dictionary)
+        .unwrap_or_default()
+}
+
+/// COVER: the corpus is split into one epoch per segment, and from each epoch the
+/// segment of k bytes whose distinct d-grams occur in the most samples is taken;
+/// the d-grams of taken segments no longer count. The best segments go last,
+/// closest to the input
+fn cover(samples: &[&[u8]], size: usize, k: usize) -> Vec<u8> {
+    let corpus = samples.concat();
+    if corpus.len() <= size {
+        return corpus;
+    }
+
+    // number of samples containing each d-gram
+    let mut freqs: HashMap<u64, u32> = HashMap::new();
+    for sample in samples {
+        for x in dmers(sample).collect::<HashSet<_>>() {
+            *freqs.entry(x).or_default() += 1;
+        }
+    }
+
+    let k = k.max(DMER_LEN);
+    // no room for a segment, or no segment of k bytes
+    if size == 0 || k > corpus.len() {
+        return Vec::new();
+    }
+    let n = size.div_ceil(k);
+    let epoch_len = corpus.len() / n;
+    let mut segments = Vec::new();
+    for epoch in 0..n {
+        let begin = epoch * epoch_len;
+        let end = (begin + epoch_len).min(corpus.len() + 1 - k);
+        if begin >= end {
+            break;
+        }
+        // sliding window over the d-grams of corpus[start..start + k]
+        let mut active: HashMap<u64, u32> = HashMap::new();
+        let mut score = 0;
+        let (mut best_score, mut best_start) = (0, begin);
+        for start in begin..end {
+            if start == begin {
+                for x in dmers(&corpus[start..start + k]) {
+                    let count = active.entry(x).or_default();
+                    if *count == 0 {
+                        score += freqs.get(&x).copied().unwrap_or(0);
+                    }
+                    *count += 1;
+                }
+            } else {
+                let x = dmer(&corpus, start - 1);
+                let count = active.get_mut(&x).unwrap();
+                *count -= 1;
+                if *count == 0 {
+                    score -= freqs.get(&x).copied().unwrap_or(0);
+                }
+                let x = dmer(&corpus, start + k - DMER_LEN);
+                let count = active.entry(x).or_default();
+                if *count == 0 {
+                    score += freqs.get(&x).copied().unwrap_or(0);
+                }
+                *count += 1;
+            }
+            if score > best_score {
+                (best_score, best_start) = (score, start);
+            }
+        }
+        if best_score == 0 {
+            continue;
+        }
+        let segment = &corpus[best_start..best_start + k];
+        for x in dmers(segment) {
+            freqs.insert(x, 0);
+        }
+        segments.push((best_score, segment));
+    }
+
+    segments.sort_by_key(|&(score, _)| score);
+    let dictionary: Vec<u8> = segments.into_iter().flat_map(|(_, x)| x.to_vec()).collect();
+    dictionary[dictionary.len().saturating_sub(size)..].to_vec()
+}
@@ -1,159 +1,400 @@
-use crate::hash::RunningHasher;
-
-use crate::hash_chain::HashChain;
-use crate::lz77::{Lz77, MAX_DISTANCE, MAX_LENGTH};
+use crate::cost::{CostModel, TokenCount};
+use crate::hash::{
+    HashFunction, DEFAULT_HASH_BITS, DEFAULT_HASH_LEN, MAX_HASH_BITS, MAX_HASH_LEN, MIN_HASH_BITS,
+    MIN_HASH_LEN,
+};
+use crate::lz77::{
+    Lz77, DEFAULT_WINDOW_BITS, MAX_LENGTH, MAX_WINDOW_BITS, MIN_LENGTH, MIN_WINDOW_BITS,
+};
+use crate::match_finder::{AnyMatchFinder, MatchFinder, MatchFinderKind};
+use crate::token::Vocab;
+use std::collections::VecDeque;
 use std::io::Read;
 use std::io::Result;
 
-const BUF_LEN: usize = 1 << 16; // 64k
-const BUF_MASK: usize = BUF_LEN - 1;
-const READ_CHUNK_SIZE: usize = 1 << 14; // 16k -- must be a few bytes less than MAX_DISTANCE
-const CHAIN_LEN: usize = 1 << 15; // 32k
-const CHAIN_MASK: usize = CHAIN_LEN - 1;
+/// the buffer holds two windows, and at least 64k
+const MIN_BUF_LEN: usize = 1 << 16; // 64k
+const READ_CHUNK_SIZE: usize = 1 << 14; // 16k -- must be a few bytes less than half the buffer
+/// bytes that should be ready to read before pulling the next code from an incrementally
+/// fed reader, such that the lookahead is never cut short by a read that comes up empty
+pub const MIN_READ_AHEAD: usize = 2 * READ_CHUNK_SIZE;
+pub const DEFAULT_MIN_MATCH: usize = 4;
+const OPTIMAL_BLOCK_LEN: usize = 1 << 12; // 4k -- literals are read back from buf, so keep it well below MIN_BUF_LEN - READ_CHUNK_SIZE
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub enum Parsing {
+    /// greedy matching with one step of lazy lookahead
+    #[default]
+    Lazy,
+    /// least total cost within each block, found by dynamic programming;
+    /// the cost defaults to the number of tokens
+    Optimal,
+}
+
+/// Tuning knobs for the match search, analogous to zlib's configuration table
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub struct EncoderConfig {
+    /// maximum number of hash chain entries to visit per search
+    pub max_chain: usize,
+    /// once the current match is at least this long, the lazy search
+    /// visits only a quarter of max_chain
+    pub good_length: usize,
+    /// stop searching as soon as a match of this length is found
+    pub nice_length: usize,
+    /// only look for a better match at the next position if the current
+    /// match is shorter than this
+    pub max_lazy: usize,
+    pub parsing: Parsing,
+    /// matches reach back at most 2^window_bits bytes, MIN_WINDOW_BITS ~ MAX_WINDOW_BITS;
+    /// the token vocabulary grows with it, see Vocab
+    pub window_bits: u32,
+    /// hash of the first hash_len bytes of a position, MIN_HASH_LEN ~ MAX_HASH_LEN,
+    /// that the hash chain or binary tree buckets positions by; longer hashes suit
+    /// binary data. SuffixArray ranks whole suffixes and ignores the hash
+    pub hash_function: HashFunction,
+    pub hash_len: usize,
+    /// the hash table has 2^hash_bits entries, MIN_HASH_BITS ~ MAX_HASH_BITS;
+    /// larger tables keep the chains of big windows short
+    pub hash_bits: u32,
+    /// shorter matches are emitted as literals, 3 ~ MAX_LENGTH; the hash chain and
+    /// the binary tree only find matches of at least hash_len bytes
+    pub min_match: usize,
+    /// the match finder of Encoder::with_config and Encoder::with_cost_model
+    pub match_finder: MatchFinderKind,
+}
+
+impl Default for EncoderConfig {
+    fn default() -> Self {
+        Self {
+            max_chain: 1024,
+            good_length: MAX_LENGTH,
+            nice_length: MAX_LENGTH,
+            max_lazy: MAX_LENGTH,
+            parsing: Parsing::Lazy,
+            window_bits: DEFAULT_WINDOW_BITS,
+            hash_function: HashFunction::ShiftXor,
+            hash_len: DEFAULT_HASH_LEN,
+            hash_bits: DEFAULT_HASH_BITS,
+            min_match: DEFAULT_MIN_MATCH,
+            match_finder: MatchFinderKind::HashChain,
+        }
+    }
+}
 
-pub struct Encoder<R> {
+impl EncoderConfig {
+    /// zlib-like compression level from 0 (literals only) to 9 (slowest)
+    pub fn level(level: u32) -> Option<Self> {
+        // good_length, max_lazy, nice_length, max_chain
+        let (good_length, max_lazy, nice_length, max_chain) = match level {
+            0 => (0, 0, 0, 0),
+            1 => (4, 0, 8, 4),
+            2 => (4, 0, 16, 8),
+            3 => (4, 0, 32, 32),
+            4 => (4, 4, 16, 16),
+            5 => (8, 16, 32, 32),
+            6 => (8, 16, 128, 128),
+            7 => (8, 32, 128, 256),
+            8 => (32, 128, 258, 1024),
+            9 => (32, 258, 258, 4096),
+            _ => return None,
+        };
+        Some(Self {
+            max_chain,
+            good_length,
+            nice_length,
+            max_lazy,
+            ..Self::default()
+        })
+    }
+
+    pub fn max_distance(&self) -> usize {
+        1 << self.window_bits
+    }
+
+    /// token ids for this window
+    pub fn vocab(&self) -> Vocab {
+        Vocab::new(self.window_bits).expect("invalid window_bits")
+    }
+
+    fn validate(&self) {
+        assert!(
+            (MIN_WINDOW_BITS..=MAX_WINDOW_BITS).contains(&self.window_bits),
+            "invalid window_bits"
+        );
+        assert!(
+            (MIN_HASH_LEN..=MAX_HASH_LEN).contains(&self.hash_len),
+            "invalid hash_len"
+        );
+        assert!(
+            (MIN_HASH_BITS..=MAX_HASH_BITS).contains(&self.hash_bits),
+            "invalid hash_bits"
+        );
+        assert!(
+            (MIN_LENGTH..=MAX_LENGTH).contains(&self.min_match),
+            "invalid min_match"
+        );
+    }
+}
+
+pub struct Encoder<R, C = TokenCount, M = AnyMatchFinder> {
     read: R,
     search_pos: usize,
     cap: usize,
     buf: Vec<u8>,
-    hasher: RunningHasher,
-    hash_chain: HashChain,
-    pos2hash: Vec<u16>,
+    buf_mask: usize,
+    match_finder: M,
+    config: EncoderConfig,
     verbose: bool,
     state: Option<(usize, usize)>, // length, distance
+    queue: VecDeque<Lz77>,
+    cost_model: C,
+    dict_len: usize,
+    primed: bool,
 }
 
 impl<R: Read> Encoder<R> {
     pub fn new(read: R, verbose: bool) -> Self {
+        Self::with_config(read, EncoderConfig::default(), verbose)
+    }
+
+    pub fn with_config(read: R, config: EncoderConfig, verbose: bool) -> Self {
+        Self::with_cost_model(read, config, TokenCount, verbose)
+    }
+}
+
+impl<R: Read, C: CostModel> Encoder<R, C> {
+    /// the cost model only steers Parsing::Optimal
+    pub fn with_cost_model(read: R, config: EncoderConfig, cost_model: C, verbose: bool) -> Self {
+        Self::with_match_finder(
+            read,
+            config,
+            cost_model,
+            AnyMatchFinder::new(&config),
+            verbose,
+        )
+    }
+}
+
+impl<R: Read, C: CostModel, M: MatchFinder> Encoder<R, C, M> {
+    /// with another match finder than config.match_finder, e.g., SuffixArray::new(xs, &config)
+    pub fn with_match_finder(
+        read: R,
+        config: EncoderConfig,
+        cost_model: C,
+        match_finder: M,
+        verbose: bool,
+    ) -> Self {
+        config.validate();
+        let window = config.max_distance();
+        let buf_len = (2 * window).max(MIN_BUF_LEN);
         Self {
             read,
             search_pos: 0,
             cap: 0,
-            buf: vec![0; BUF_LEN],
-            hasher: RunningHasher::new(),
-            hash_chain: HashChain::new(),
-            pos2hash: vec![0; CHAIN_LEN],
+            buf: vec![0; buf_len],
+            buf_mask: buf_len - 1,
+            match_finder,
+            config,
             verbose,
             state: None,
+            queue: VecDeque::new(),
+            cost_model,
+            dict_len: 0,
+            primed: false,
+        }
+    }
+
+    /// pre-fill the window with a preset dictionary, only the last max_distance bytes of
+    /// which can be referenced, like zlib's deflateSetDictionary; the decoder must use
+    /// the same dictionary
+    pub fn with_dictionary(mut self, dictionary: &[u8]) -> Self {
+        assert!(
+            self.search_pos == 0,
+            "dictionary must be set before encoding"
+        );
+        let max_distance = self.config.max_distance();
+        let dictionary = &dictionary[dictionary.len().saturating_sub(max_distance)..];
+        if dictionary.is_empty() {
+            return self;
+        }
+        // position 0 is never matched, as it marks the end of a hash chain
+        let n = dictionary.len() + 1;
+        self.buf[1..n].copy_from_slice(dictionary);
+        self.cap = n;
+        // the last positions are indexed along with the input, see prime_hash
+        for pos in 1..(n + 1).saturating_sub(self.match_finder.hash_len()) {
+            self.search_pos = pos;
+            self.insert();
         }
+        self.search_pos = n;
+        self.dict_len = n;
+        self
     }
 
-    /// fill up the buffer by at most READ_CHUNK_SIZE
+    /// fill up the buffer by READ_CHUNK_SIZE, or up to its end, unless the input ends
+    /// first; a reader may return less than it has, e.g., a VecDeque whose ring has
+    /// wrapped around, so read until the chunk is full, as a slice would fill it
     fn fill_buf(&mut self) -> Result<()> {
         if self.cap >= self.search_pos + MAX_LENGTH {
             return Ok(());
         }
-        let cap = self.cap & BUF_MASK;
-        let n = (BUF_LEN - cap).min(READ_CHUNK_SIZE);
-        let n = self.read.read(&mut self.buf[cap..cap + n])?;
-        self.cap += n;
-        Ok(())
-    }
-
-    /// update the hash and return the most recent position that has hash clash
-    fn advance_hash(&mut self) -> usize {
-        let hash_pos = self.search_pos + 2;
-        match hash_pos & BUF_MASK {
-            0x0000 => {
-                self.hash_chain.prune_table(0x8000..);
-                self.hash_chain.prune_chain(0x8000..);
-            }
-            0x8000 => {
-                self.hash_chain.prune_table(0x0000..0x8000);
-                self.hash_chain.prune_chain(0x0000..0x8000);
+        let cap = self.cap & self.buf_mask;
+        let end = cap + (self.buf.len() - cap).min(READ_CHUNK_SIZE);
+        let mut filled = cap;
+        while filled < end {
+            let n = self.read.read(&mut self.buf[filled..end])?;
+            if n == 0 {
+                break;
             }
-            _ => {}
+            filled += n;
         }
-
-        let x = self.buf[hash_pos & BUF_MASK];
-        if self.verbose {
-            eprintln!("update hash for {}", hash_pos);
-        }
-        let h = self.hasher.update(x);
-
-        let x = (self.search_pos & BUF_MASK) as u16;
-        let prev_idx = self.hash_chain.add(h, x);
-        self.pos2hash[(self.search_pos) & CHAIN_MASK] = h as u16;
-        prev_idx as usize
+        self.cap += filled - cap;
+        Ok(())
     }
 
-    fn match_length(&self, begin: usize, end: usize, target: usize) -> usize {
-        let mut result = 0;
-        for (ix1, ix2) in (begin..end).zip(target..) {
-            if self.buf[ix1 & BUF_MASK] != self.buf[ix2 & BUF_MASK] {
-                break;
-            }
-            result += 1;
+    /// index the current position
+    fn insert(&mut self) {
+        if self.verbose {
+            eprintln!("update hash for {}", self.search_pos);
         }
-        result
+        self.match_finder.skip(&self.buf, self.search_pos, self.cap);
     }
 
-    /// search for best match that is least greater than best_length
-    fn best_match(
-        &self,
-        mut pos: usize,
-        mut best_length: usize,
-        mut max_count: usize,
+    /// index the current position and search for the best match that is longer than
+    /// best_length, calling on_match for every improving (length, distance)
+    fn find_match<F: FnMut(usize, usize)>(
+        &mut self,
+        best_length: usize,
+        max_count: usize,
+        mut on_match: F,
     ) -> (usize, usize) {
-        let mut best_distance = 0;
-        let mut prev_distance = 0;
-        let upper_bound = MAX_LENGTH.min(self.cap - self.search_pos);
-
-        while max_count > 0 && pos != 0 && best_length < upper_bound {
-            let distance = (self.search_pos - pos) & 0xFFFF;
-            debug_assert_eq!(
-                distance & BUF_MASK,
-                (self.search_pos - (pos & BUF_MASK)) & BUF_MASK
-            );
-
-            debug_assert_ne!(distance, 0);
-            if prev_distance >= distance || distance > MAX_DISTANCE {
-                break;
-            }
-
-            debug_assert_eq!(self.pos2hash[pos & CHAIN_MASK], self.hasher.get() as u16);
-
-            if self.verbose {
-                eprintln!("pos: {}\tdistance: {}", self.search_pos, distance);
-            }
-
-            if self.buf[(self.search_pos + best_length) & BUF_MASK]
-                == self.buf[(pos + best_length) & BUF_MASK]
-            {
-                let length = self.match_length(self.search_pos, self.search_pos + upper_bound, pos);
-                if length > best_length {
-                    best_length = length;
-                    best_distance = distance;
+        let (pos, verbose) = (self.search_pos, self.verbose);
+        self.match_finder
+            .find(&self.buf, pos, self.cap, best_length, max_count, |l, d| {
+                if verbose {
+                    eprintln!("pos: {}\tlength: {}\tdistance: {}", pos, l, d);
                 }
-            }
-
-            pos = self.hash_chain.get(pos) as usize;
-            prev_distance = distance;
-            max_count -= 1;
-        }
-
-        (best_length, best_distance)
+                on_match(l, d)
+            })
     }
 
     /// returns length so far, new (length, distance)
-    fn better_match(&mut self, length: usize, max_count: usize) -> Option<(usize, usize, usize)> {
+    fn better_match(&mut self, length: usize) -> Option<(usize, usize, usize)> {
+        let lazy = length < self.config.max_lazy;
+        let max_count = if length >= self.config.good_length {
+            self.config.max_chain / 4
+        } else {
+            self.config.max_chain
+        };
         for ix in 1..length {
-            let pos = self.advance_hash();
-            if ix == 1 {
-                let (l, d) = self.best_match(pos, length, max_count);
+            if ix == 1 && lazy {
+                let (l, d) = self.find_match(length, max_count, |_, _| {});
                 self.search_pos += 1;
                 if d > 0 {
                     return Some((ix, l, d));
                 }
             } else {
+                self.insert();
                 self.search_pos += 1;
             }
         }
         None
     }
 
+    fn prime_hash(&mut self) {
+        if self.primed {
+            return;
+        }
+        self.primed = true;
+        if self.dict_len == 0 {
+            return;
+        }
+        let begin = self.search_pos;
+        let first = (self.dict_len + 1).saturating_sub(self.match_finder.hash_len());
+        for pos in first.max(1)..self.dict_len {
+            self.search_pos = pos;
+            self.insert();
+        }
+        self.search_pos = begin;
+    }
+
+    /// scan the next block, collecting all improving matches at every position,
+    /// then queue up the parse with the least cost
+    fn optimize_block(&mut self) -> Result<()> {
+        let begin = self.search_pos;
+        let mut matches = Vec::new();
+        let mut ends = vec![0];
+        while self.search_pos - begin < OPTIMAL_BLOCK_LEN {
+            self.fill_buf()?;
+            if self.search_pos >= self.cap {
+                break;
+            }
+            self.prime_hash();
+            let min_match = self.config.min_match;
+            self.find_match(min_match - 1, self.config.max_chain, |l, d| {
+                matches.push((l, d))
+            });
+            self.search_pos += 1;
+            ends.push(matches.len());
+        }
+
+        // costs[i]: least cost to reach begin + i; steps[i]: (length, distance) of the last step
+        let n = self.search_pos - begin;
+        let mut costs = vec![f32::INFINITY; n + 1];
+        let mut steps = vec![(0, 0); n + 1];
+        costs[0] = 0.0;
+        for i in 0..n {
+            let cost = costs[i]
+                + self
+                    .cost_model
+                    .literal(self.buf[(begin + i) & self.buf_mask]);
+            if cost < costs[i + 1] {
+                costs[i + 1] = cost;
+                steps[i + 1] = (1, 0);
+            }
+            let cost = costs[i];
+            // each length is served by the nearest match that covers it
+            let mut length = self.config.min_match;
+            for &(l, d) in &matches[ends[i]..ends[i + 1]] {
+                while length <= l.min(n - i) {
+                    let cost = cost + self.cost_model.dictionary(length, d);
+                    if cost < costs[i + length] {
+                        costs[i + length] = cost;
+                        steps[i + length] = (length, d);
+                    }
+                    length += 1;
+                }
+            }
+        }
+
+        let mut i = n;
+        while i > 0 {
+            let (length, distance) = steps[i];
+            i -= length;
+            let code = if length == 1 {
+                Lz77::Literal(self.buf[(begin + i) & self.buf_mask])
+            } else {
+                Lz77::Dictionary {
+                    length: (length - 3) as u8,
+                    distance: distance as u32,
+                }
+            };
+            self.queue.push_front(code);
+        }
+        Ok(())
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.read
+    }
+
+    /// number of bytes consumed, not counting the dictionary
     pub fn len(&self) -> usize {
-        self.search_pos
+        self.search_pos - self.dict_len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
     }
 
     /// read up n bytes; it will return as soon as it reads >= n bytes
@@ -170,51 +411,54 @@ impl<R: Read> Encoder<R> {
     }
 }
 
-impl<R: Read> Iterator for Encoder<R> {
+impl<R: Read, C: CostModel, M: MatchFinder> Iterator for Encoder<R, C, M> {
     type Item = Lz77;
 
     fn next(&mut self) -> Option<Self::Item> {
+        if self.config.parsing == Parsing::Optimal {
+            if self.queue.is_empty() {
+                self.optimize_block().unwrap();
+            }
+            return self.queue.pop_front();
+        }
+
         self.fill_buf().unwrap();
         if self.search_pos >= self.cap {
             return None;
         }
-
-        if self.search_pos == 0 {
-            self.hasher.update(self.buf[0]);
-            self.hasher.update(self.buf[1]);
-        }
+        self.prime_hash();
 
         let (length, distance) = if self.state.is_some() {
             self.state.take().unwrap()
         } else {
-            let pos = self.advance_hash();
-            let (l, d) = self.best_match(pos, 3, 1024);
+            let min_match = self.config.min_match;
+            let (l, d) = self.find_match(min_match - 1, self.config.max_chain, |_, _| {});
             self.search_pos += 1;
             (l, d)
         };
 
-        if length < 4 {
-            let x = self.buf[(self.search_pos - 1) & BUF_MASK];
+        if length < self.config.min_match {
+            let x = self.buf[(self.search_pos - 1) & self.buf_mask];
             return Some(Lz77::Literal(x));
         }
 
-        if let Some((l1, l2, d)) = self.better_match(length, 1024) {
+        if let Some((l1, l2, d)) = self.better_match(length) {
             self.state = Some((l2, d));
             match l1 {
                 1 => {
-                    let x = self.buf[(self.search_pos - 2) & BUF_MASK];
+                    let x = self.buf[(self.search_pos - 2) & self.buf_mask];
                     Some(Lz77::Literal(x))
                 }
                 4.. => Some(Lz77::Dictionary {
                     length: (l1 - 3) as u8,
-                    distance: distance as u16,
+                    distance: distance as u32,
                 }),
                 _ => unreachable!(),
             }
         } else {
             Some(Lz77::Dictionary {
                 length: (length - 3) as u8,
-                distance: distance as u16,
+                distance: distance as u32,
             })
         }
     }
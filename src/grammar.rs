@@ -0,0 +1,153 @@
+use crate::decoder::DecodeError;
+use crate::token::{MetaToken, Token, Vocab};
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+enum State {
+    /// expecting Literal or Length
+    Symbol,
+    /// expecting Distance0 after Length
+    Distance0,
+    /// expecting Distance1 after Distance0
+    Distance1(u8),
+    /// after EndOfSentence, only Pad may follow
+    End,
+}
+
+/// Tracks a token prefix and tells which tokens may follow, such that
+/// sampling only generates sequences that DecoderAdapter accepts
+#[derive(Clone, Debug)]
+pub struct TokenGrammar {
+    state: State,
+    position: usize, // number of tokens pushed
+    len: usize,      // number of bytes decoded by complete symbols
+    length: usize,   // length of the pending match
+    vocab: Vocab,
+}
+
+impl Default for TokenGrammar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TokenGrammar {
+    pub fn new() -> Self {
+        Self::with_vocab(Vocab::default())
+    }
+
+    /// token ids in mask() and the largest distance follow the vocabulary
+    pub fn with_vocab(vocab: Vocab) -> Self {
+        Self {
+            state: State::Symbol,
+            position: 0,
+            len: 0,
+            length: 0,
+            vocab,
+        }
+    }
+
+    pub fn vocab(&self) -> Vocab {
+        self.vocab
+    }
+
+    /// number of u64 words in mask()
+    pub fn mask_len(&self) -> usize {
+        self.vocab.size().div_ceil(64)
+    }
+
+    /// number of tokens pushed so far
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// number of bytes the prefix decodes to, excluding a pending match
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.position == 0
+    }
+
+    /// whether the prefix ends on a symbol boundary
+    pub fn is_complete(&self) -> bool {
+        matches!(self.state, State::Symbol | State::End)
+    }
+
+    fn max_distance(&self) -> usize {
+        self.len.min(self.vocab.max_distance())
+    }
+
+    pub fn is_valid(&self, token: Token) -> bool {
+        match (self.state, token) {
+            (State::Symbol, Token::Literal(_)) => true,
+            (State::Symbol, Token::Length(_)) => self.len > 0,
+            (State::Symbol, Token::Meta(MetaToken::StartOfSentence)) => self.len == 0,
+            (State::Symbol, Token::Meta(_)) => true,
+            (State::End, Token::Meta(MetaToken::Pad)) => true,
+            (State::Distance0, Token::Distance0(d0)) => (d0 as usize) < self.max_distance(),
+            (State::Distance1(d0), Token::Distance1(d1)) => {
+                (d0 as usize | (d1 as usize) << 8) < self.max_distance()
+            }
+            _ => false,
+        }
+    }
+
+    pub fn push(&mut self, token: Token) -> Result<(), DecodeError> {
+        if !self.is_valid(token) {
+            return Err(DecodeError::UnexpectedToken {
+                position: self.position,
+                token,
+            });
+        }
+        self.state = match token {
+            Token::Literal(_) => {
+                self.len += 1;
+                State::Symbol
+            }
+            Token::Length(l) => {
+                self.length = l as usize + 3;
+                State::Distance0
+            }
+            Token::Distance0(d0) => State::Distance1(d0),
+            Token::Distance1(_) => {
+                self.len += self.length;
+                State::Symbol
+            }
+            Token::Meta(MetaToken::EndOfSentence) => State::End,
+            Token::Meta(_) => self.state,
+        };
+        self.position += 1;
+        Ok(())
+    }
+
+    /// bit i of the mask is set if token id i is valid next
+    pub fn mask(&self) -> Vec<u64> {
+        let mut mask = vec![0; self.mask_len()];
+        let mut set = |first: Token, n: usize| {
+            let begin = self.vocab.id(first) as usize;
+            for id in begin..begin + n {
+                mask[id / 64] |= 1 << (id % 64);
+            }
+        };
+        match self.state {
+            State::Symbol => {
+                set(Token::Literal(0), 256);
+                if self.len > 0 {
+                    set(Token::Length(0), 256);
+                } else {
+                    set(Token::Meta(MetaToken::StartOfSentence), 1);
+                }
+                set(Token::Meta(MetaToken::EndOfSentence), 1);
+                set(Token::Meta(MetaToken::Pad), 1);
+            }
+            State::Distance0 => set(Token::Distance0(0), self.max_distance().min(256)),
+            State::Distance1(d0) => {
+                let n = (self.max_distance() - 1 - d0 as usize) / 256 + 1;
+                set(Token::Distance1(0), n);
+            }
+            State::End => set(Token::Meta(MetaToken::Pad), 1),
+        }
+        mask
+    }
+}
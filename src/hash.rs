@@ -1,7 +1,40 @@
-pub const TABLE_SIZE: usize = 0x8000;
+pub const DEFAULT_HASH_LEN: usize = 3;
+pub const DEFAULT_HASH_BITS: u32 = 15;
+pub const MIN_HASH_LEN: usize = 3;
+pub const MAX_HASH_LEN: usize = 5;
+pub const MIN_HASH_BITS: u32 = 8;
+pub const MAX_HASH_BITS: u32 = 24;
 
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub enum HashFunction {
+    /// zlib's: shift the hash and xor in each byte, so the oldest byte falls off
+    /// after len updates; cheap, but spreads text better than binary data
+    #[default]
+    ShiftXor,
+    /// Fibonacci hashing of the last len bytes, i.e., the top bits of their product
+    /// with 2^64 / phi; mixes all the bits, as LZ4 and zstd do
+    Multiplicative,
+}
+
+impl HashFunction {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "shift-xor" => Some(Self::ShiftXor),
+            "multiplicative" => Some(Self::Multiplicative),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct RunningHasher {
     hash: usize,
+    /// last len bytes, the most recent one lowest
+    bytes: u64,
+    function: HashFunction,
+    bytes_mask: u64,
+    bits: u32,
+    shift: u32,
 }
 
 impl Default for RunningHasher {
@@ -13,16 +46,41 @@ impl Default for RunningHasher {
 impl RunningHasher {
     /// 3-byte hasher, i.e., the hash is a function of the last three bytes
     /// uses 15-bits hash, so shift is 5-bits
-    const MASK: usize = TABLE_SIZE - 1;
-    const SHIFT: usize = 5;
-
     pub fn new() -> Self {
-        Self { hash: 0 }
+        Self::with_params(HashFunction::ShiftXor, DEFAULT_HASH_LEN, DEFAULT_HASH_BITS)
+    }
+
+    /// the hash is a function of the last len bytes, MIN_HASH_LEN ~ MAX_HASH_LEN,
+    /// and lies below 2^bits, MIN_HASH_BITS ~ MAX_HASH_BITS, the hash table size
+    pub fn with_params(function: HashFunction, len: usize, bits: u32) -> Self {
+        assert!(
+            (MIN_HASH_LEN..=MAX_HASH_LEN).contains(&len),
+            "invalid hash len"
+        );
+        assert!(
+            (MIN_HASH_BITS..=MAX_HASH_BITS).contains(&bits),
+            "invalid hash bits"
+        );
+        Self {
+            hash: 0,
+            bytes: 0,
+            function,
+            bytes_mask: (1 << (8 * len)) - 1,
+            bits,
+            shift: bits.div_ceil(len as u32),
+        }
     }
 
     pub fn update(&mut self, x: u8) -> usize {
-        self.hash = (self.hash << Self::SHIFT) ^ x as usize;
-        self.hash &= Self::MASK;
+        self.hash = match self.function {
+            HashFunction::ShiftXor => {
+                ((self.hash << self.shift) ^ x as usize) & ((1 << self.bits) - 1)
+            }
+            HashFunction::Multiplicative => {
+                self.bytes = (self.bytes << 8 | x as u64) & self.bytes_mask;
+                (self.bytes.wrapping_mul(0x9E37_79B9_7F4A_7C15) >> (64 - self.bits)) as usize
+            }
+        };
         self.hash
     }
 
@@ -43,4 +101,17 @@ fn test_rolling_hash() {
     let h2 = hasher.update(b'c');
 
     assert_eq!(h1, h2);
+
+    for function in [HashFunction::ShiftXor, HashFunction::Multiplicative] {
+        for len in MIN_HASH_LEN..=MAX_HASH_LEN {
+            let mut hasher = RunningHasher::with_params(function, len, 20);
+            let hashes: Vec<usize> = b"xyzabcdeqabcde"
+                .iter()
+                .map(|&x| hasher.update(x))
+                .collect();
+            assert!(hashes.iter().all(|&h| h < 1 << 20));
+            // the same last len bytes
+            assert_eq!(hashes[7], hashes[13]);
+        }
+    }
 }
@@ -1,50 +1,37 @@
-use std::ops::RangeBounds;
-
-use crate::hash::TABLE_SIZE;
-
-const CHAIN_LEN: u16 = 1 << 15; // 32k
-const CHAIN_MASK: u16 = CHAIN_LEN - 1;
-
+/// Positions with the same hash, chained from the most recent one. Positions are
+/// absolute, modulo 2^32, with 0 marking the end of a chain; entries are never
+/// cleared, so a chain runs on into positions that have left the window, whose
+/// entries may since have been reused by newer positions. Callers stop at the first
+/// position farther than the window, or not farther than the one before it
 pub struct HashChain {
-    table: Vec<u16>,
-    chain: Vec<u16>,
+    table: Vec<u32>,
+    chain: Vec<u32>,
+    chain_mask: usize,
 }
 
 impl HashChain {
-    pub fn new() -> Self {
+    /// table_size: number of hash values, see RunningHasher::with_params
+    /// len: number of chain entries, a power of two no less than the window
+    pub fn with_sizes(table_size: usize, len: usize) -> Self {
+        assert!(len.is_power_of_two());
         Self {
-            table: vec![0; TABLE_SIZE],
-            chain: vec![0; CHAIN_LEN as usize],
+            table: vec![0; table_size],
+            chain: vec![0; len],
+            chain_mask: len - 1,
         }
     }
 
     /// hash: masked hash
-    /// x: unmasked new item
-    pub fn add(&mut self, hash: usize, x: u16) -> u16 {
+    /// x: new position
+    pub fn add(&mut self, hash: usize, x: u32) -> u32 {
         let prev = self.table[hash];
         self.table[hash] = x;
-        self.chain[(x & CHAIN_MASK) as usize] = prev;
+        self.chain[x as usize & self.chain_mask] = prev;
         prev
     }
 
-    pub fn get(&self, x: usize) -> u16 {
-        self.chain[x & (CHAIN_MASK as usize)]
-    }
-
-    /// remove any entries if not in the range
-    pub fn prune_table<R: RangeBounds<u16>>(&mut self, range: R) {
-        for x in &mut self.table {
-            if !range.contains(x) {
-                *x = 0;
-            }
-        }
-    }
-
-    pub fn prune_chain<R: RangeBounds<u16>>(&mut self, range: R) {
-        for x in &mut self.chain {
-            if !range.contains(x) {
-                *x = 0;
-            }
-        }
+    /// the position before x in its chain, valid while x is within len of the last one added
+    pub fn get(&self, x: u32) -> u32 {
+        self.chain[x as usize & self.chain_mask]
     }
 }
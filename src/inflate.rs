@@ -0,0 +1,480 @@
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::io::{self, Read};
+
+use crate::checksum::{Adler32, Crc32};
+use crate::deflate::{
+    canonical_codes, fixed_lengths, Format, CODE_LENGTH_ORDER, DIST_BASE, DIST_EXTRA, END_OF_BLOCK,
+    LENGTH_BASE, LENGTH_EXTRA,
+};
+use crate::lz77::{Lz77, MAX_DISTANCE};
+
+const READ_CHUNK_SIZE: usize = 1 << 14; // 16k
+const WINDOW_MASK: usize = MAX_DISTANCE - 1;
+
+#[derive(Debug)]
+pub enum InflateError {
+    Io(io::Error),
+    /// stream ends before the final block or the trailer
+    UnexpectedEnd,
+    /// invalid zlib or gzip header
+    InvalidHeader,
+    InvalidBlockType,
+    /// LEN and NLEN of a stored block do not match
+    InvalidStoredLength,
+    /// code lengths that do not form a prefix code
+    InvalidCodeLengths,
+    /// bit pattern or symbol that is not part of the code
+    InvalidSymbol,
+    /// back-reference reaching before the first decoded byte
+    InvalidDistance {
+        distance: usize,
+    },
+    ChecksumMismatch,
+}
+
+impl Display for InflateError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{}", e),
+            Self::UnexpectedEnd => write!(f, "unexpected end of stream"),
+            Self::InvalidHeader => write!(f, "invalid header"),
+            Self::InvalidBlockType => write!(f, "invalid block type"),
+            Self::InvalidStoredLength => write!(f, "invalid stored block length"),
+            Self::InvalidCodeLengths => write!(f, "invalid code lengths"),
+            Self::InvalidSymbol => write!(f, "invalid symbol"),
+            Self::InvalidDistance { distance } => write!(f, "invalid distance {}", distance),
+            Self::ChecksumMismatch => write!(f, "checksum mismatch"),
+        }
+    }
+}
+
+impl Error for InflateError {}
+
+impl From<io::Error> for InflateError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+type Result<T> = std::result::Result<T, InflateError>;
+
+/// LSB-first bit unpacking
+struct BitReader<R> {
+    read: R,
+    buf: Vec<u8>,
+    pos: usize,
+    cap: usize,
+    bits: u64,
+    n: u32,
+}
+
+impl<R: Read> BitReader<R> {
+    fn new(read: R) -> Self {
+        Self {
+            read,
+            buf: vec![0; READ_CHUNK_SIZE],
+            pos: 0,
+            cap: 0,
+            bits: 0,
+            n: 0,
+        }
+    }
+
+    /// load bits until there are at least n, unless the stream ends
+    fn fill(&mut self, n: u32) -> Result<()> {
+        while self.n < n {
+            if self.pos == self.cap {
+                self.cap = self.read.read(&mut self.buf)?;
+                self.pos = 0;
+                if self.cap == 0 {
+                    return Ok(());
+                }
+            }
+            self.bits |= (self.buf[self.pos] as u64) << self.n;
+            self.pos += 1;
+            self.n += 8;
+        }
+        Ok(())
+    }
+
+    /// next n bits without consuming them, zero-padded past the end of the stream
+    fn peek(&mut self, n: u32) -> Result<u32> {
+        self.fill(n)?;
+        Ok((self.bits & ((1 << n) - 1)) as u32)
+    }
+
+    fn consume(&mut self, n: u32) -> Result<()> {
+        if n > self.n {
+            return Err(InflateError::UnexpectedEnd);
+        }
+        self.bits >>= n;
+        self.n -= n;
+        Ok(())
+    }
+
+    /// n <= 32
+    fn read_bits(&mut self, n: u32) -> Result<u32> {
+        let x = self.peek(n)?;
+        self.consume(n)?;
+        Ok(x)
+    }
+
+    fn align(&mut self) {
+        self.bits >>= self.n % 8;
+        self.n -= self.n % 8;
+    }
+
+    /// whether nothing but zeros is left, after aligning to the byte boundary, which
+    /// are skipped; gzip -d ignores them after the last member, e.g., block padding
+    fn is_zero_padding(&mut self) -> Result<bool> {
+        self.align();
+        loop {
+            self.fill(8)?;
+            if self.n == 0 {
+                return Ok(true);
+            }
+            if self.peek(8)? != 0 {
+                return Ok(false);
+            }
+            self.consume(8)?;
+        }
+    }
+
+    fn read_u8(&mut self) -> Result<u8> {
+        Ok(self.read_bits(8)? as u8)
+    }
+
+    fn read_u16_le(&mut self) -> Result<u16> {
+        Ok(self.read_bits(16)? as u16)
+    }
+
+    fn read_u32_le(&mut self) -> Result<u32> {
+        self.read_bits(32)
+    }
+}
+
+/// Lookup table indexed by the next bits; entry: symbol << 4 | code length
+struct Huffman {
+    table: Vec<u16>,
+    bits: u32,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Result<Self> {
+        let bits = lengths.iter().copied().max().unwrap_or(0).max(1) as u32;
+        let kraft: u32 = lengths
+            .iter()
+            .filter(|&&length| length > 0)
+            .map(|&length| 1 << (bits - length as u32))
+            .sum();
+        if kraft > 1 << bits {
+            return Err(InflateError::InvalidCodeLengths);
+        }
+        let mut table = vec![0; 1 << bits];
+        for (symbol, (&length, &code)) in lengths.iter().zip(&canonical_codes(lengths)).enumerate()
+        {
+            if length == 0 {
+                continue;
+            }
+            let entry = (symbol as u16) << 4 | length as u16;
+            for x in (code as usize..1 << bits).step_by(1 << length) {
+                table[x] = entry;
+            }
+        }
+        Ok(Self { table, bits })
+    }
+
+    fn decode<R: Read>(&self, reader: &mut BitReader<R>) -> Result<usize> {
+        let entry = self.table[reader.peek(self.bits)? as usize];
+        let length = (entry & 0xF) as u32;
+        if length == 0 {
+            return Err(InflateError::InvalidSymbol);
+        }
+        reader.consume(length)?;
+        Ok((entry >> 4) as usize)
+    }
+}
+
+enum State {
+    /// expecting the zlib or gzip header
+    Member,
+    /// expecting a block header
+    Block,
+    /// number of bytes left in a stored block
+    Stored(usize),
+    Huffman(Huffman, Huffman),
+    /// expecting the trailer after the final block
+    Trailer,
+    Done,
+}
+
+/// Parses a DEFLATE stream into the Lz77 codes its compressor chose, without
+/// re-encoding; stored blocks become literals. Framed streams are decoded to verify
+/// the checksum, and concatenated gzip members are read one after another
+pub struct Inflater<R> {
+    reader: BitReader<R>,
+    format: Format,
+    state: State,
+    last: bool,   // whether the current block is the final one
+    total: usize, // number of bytes decoded so far
+    window: Vec<u8>,
+    crc: Crc32,
+    adler: Adler32,
+    member_len: usize, // bytes decoded from the current gzip member, as far as matches reach
+}
+
+impl<R: Read> Inflater<R> {
+    pub fn new(read: R, format: Format) -> Self {
+        let state = match format {
+            Format::Raw => State::Block,
+            Format::Zlib | Format::Gzip => State::Member,
+        };
+        Self {
+            reader: BitReader::new(read),
+            format,
+            state,
+            last: false,
+            total: 0,
+            window: vec![0; MAX_DISTANCE],
+            crc: Crc32::new(),
+            adler: Adler32::new(),
+            member_len: 0,
+        }
+    }
+
+    fn read_header(&mut self) -> Result<()> {
+        let reader = &mut self.reader;
+        match self.format {
+            Format::Raw => {}
+            Format::Zlib => {
+                let cmf = reader.read_u8()?;
+                let flg = reader.read_u8()?;
+                // deflate with at most 32k window, no preset dictionary
+                if cmf & 0x0F != 8 || cmf >> 4 > 7 || flg & 0x20 != 0 {
+                    return Err(InflateError::InvalidHeader);
+                }
+                // u16::is_multiple_of needs Rust 1.87
+                #[allow(clippy::manual_is_multiple_of)]
+                let check = (cmf as u16 * 256 + flg as u16) % 31 == 0;
+                if !check {
+                    return Err(InflateError::InvalidHeader);
+                }
+            }
+            Format::Gzip => {
+                let id1 = reader.read_u8()?;
+                let id2 = reader.read_u8()?;
+                let cm = reader.read_u8()?;
+                let flg = reader.read_u8()?;
+                if id1 != 0x1F || id2 != 0x8B || cm != 8 || flg & 0xE0 != 0 {
+                    return Err(InflateError::InvalidHeader);
+                }
+                // MTIME, XFL, OS
+                for _ in 0..6 {
+                    reader.read_u8()?;
+                }
+                if flg & 0x04 != 0 {
+                    let n = reader.read_u16_le()?;
+                    for _ in 0..n {
+                        reader.read_u8()?;
+                    }
+                }
+                // FNAME, FCOMMENT
+                for flag in [0x08, 0x10] {
+                    if flg & flag != 0 {
+                        while reader.read_u8()? != 0 {}
+                    }
+                }
+                if flg & 0x02 != 0 {
+                    reader.read_u16_le()?;
+                }
+            }
+        }
+        self.crc = Crc32::new();
+        self.adler = Adler32::new();
+        self.member_len = 0;
+        Ok(())
+    }
+
+    /// returns the state for the block body
+    fn read_block_header(&mut self) -> Result<State> {
+        self.last = self.reader.read_bits(1)? == 1;
+        match self.reader.read_bits(2)? {
+            0 => {
+                self.reader.align();
+                let len = self.reader.read_u16_le()?;
+                let nlen = self.reader.read_u16_le()?;
+                if len != !nlen {
+                    return Err(InflateError::InvalidStoredLength);
+                }
+                Ok(State::Stored(len as usize))
+            }
+            1 => {
+                let (litlen, dist) = fixed_lengths();
+                Ok(State::Huffman(Huffman::new(&litlen)?, Huffman::new(&dist)?))
+            }
+            2 => {
+                let (litlen, dist) = self.read_dynamic_lengths()?;
+                Ok(State::Huffman(Huffman::new(&litlen)?, Huffman::new(&dist)?))
+            }
+            _ => Err(InflateError::InvalidBlockType),
+        }
+    }
+
+    fn read_dynamic_lengths(&mut self) -> Result<(Vec<u8>, Vec<u8>)> {
+        let reader = &mut self.reader;
+        let hlit = reader.read_bits(5)? as usize + 257;
+        let hdist = reader.read_bits(5)? as usize + 1;
+        let hclen = reader.read_bits(4)? as usize + 4;
+        let mut code_length = [0; 19];
+        for &x in &CODE_LENGTH_ORDER[..hclen] {
+            code_length[x] = reader.read_bits(3)? as u8;
+        }
+        let code_length = Huffman::new(&code_length)?;
+
+        let mut lengths = Vec::with_capacity(hlit + hdist);
+        while lengths.len() < hlit + hdist {
+            let (x, n) = match code_length.decode(reader)? {
+                x @ 0..=15 => (x as u8, 1),
+                16 => {
+                    let &prev = lengths.last().ok_or(InflateError::InvalidCodeLengths)?;
+                    (prev, 3 + reader.read_bits(2)?)
+                }
+                17 => (0, 3 + reader.read_bits(3)?),
+                _ => (0, 11 + reader.read_bits(7)?),
+            };
+            lengths.resize(lengths.len() + n as usize, x);
+        }
+        if lengths.len() > hlit + hdist || lengths[END_OF_BLOCK] == 0 {
+            return Err(InflateError::InvalidCodeLengths);
+        }
+        let dist = lengths.split_off(hlit);
+        Ok((lengths, dist))
+    }
+
+    fn read_trailer(&mut self) -> Result<()> {
+        self.reader.align();
+        match self.format {
+            Format::Raw => {}
+            Format::Zlib => {
+                let adler = self.reader.read_u32_le()?.swap_bytes();
+                if adler != self.adler.get() {
+                    return Err(InflateError::ChecksumMismatch);
+                }
+            }
+            Format::Gzip => {
+                let crc = self.reader.read_u32_le()?;
+                let len = self.reader.read_u32_le()?;
+                if crc != self.crc.get() || len != self.member_len as u32 {
+                    return Err(InflateError::ChecksumMismatch);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// record the decoded bytes for the checksum
+    fn push(&mut self, code: Lz77) {
+        if self.format == Format::Raw {
+            return;
+        }
+        let (length, distance) = match code {
+            Lz77::Literal(x) => {
+                self.window[self.total & WINDOW_MASK] = x;
+                self.crc.update(&[x]);
+                self.adler.update(&[x]);
+                return;
+            }
+            Lz77::Dictionary { length, distance } => (length as usize + 3, distance as usize),
+        };
+        for pos in self.total..self.total + length {
+            let x = self.window[(pos - distance) & WINDOW_MASK];
+            self.window[pos & WINDOW_MASK] = x;
+            self.crc.update(&[x]);
+            self.adler.update(&[x]);
+        }
+    }
+
+    fn emit(&mut self, code: Lz77) -> Option<Result<Lz77>> {
+        self.push(code);
+        let n = match code {
+            Lz77::Literal(_) => 1,
+            Lz77::Dictionary { length, .. } => length as usize + 3,
+        };
+        self.total += n;
+        self.member_len += n;
+        Some(Ok(code))
+    }
+
+    /// Ok(None) once the stream has ended
+    fn next_code(&mut self) -> Result<Option<Lz77>> {
+        loop {
+            match &self.state {
+                State::Member => {
+                    self.read_header()?;
+                    self.state = State::Block;
+                }
+                State::Block => self.state = self.read_block_header()?,
+                State::Stored(0) | State::Trailer if !self.last => self.state = State::Block,
+                &State::Stored(n) if n > 0 => {
+                    self.state = State::Stored(n - 1);
+                    return Ok(Some(Lz77::Literal(self.reader.read_u8()?)));
+                }
+                State::Stored(_) => self.state = State::Trailer,
+                State::Huffman(litlen, dist) => {
+                    let reader = &mut self.reader;
+                    let x = litlen.decode(reader)?;
+                    if x < 256 {
+                        return Ok(Some(Lz77::Literal(x as u8)));
+                    }
+                    if x == END_OF_BLOCK {
+                        self.state = State::Trailer;
+                        continue;
+                    }
+                    let i = x - 257;
+                    if i >= LENGTH_BASE.len() {
+                        return Err(InflateError::InvalidSymbol);
+                    }
+                    let length =
+                        LENGTH_BASE[i] as u32 + reader.read_bits(LENGTH_EXTRA[i] as u32)?;
+                    let j = dist.decode(reader)?;
+                    if j >= DIST_BASE.len() {
+                        return Err(InflateError::InvalidSymbol);
+                    }
+                    let distance =
+                        DIST_BASE[j] as usize + reader.read_bits(DIST_EXTRA[j] as u32)? as usize;
+                    // members are independent, so not into the previous one
+                    if distance > self.member_len {
+                        return Err(InflateError::InvalidDistance { distance });
+                    }
+                    return Ok(Some(Lz77::Dictionary {
+                        length: (length - 3) as u8,
+                        distance: distance as u32,
+                    }));
+                }
+                State::Trailer => {
+                    self.read_trailer()?;
+                    let more = self.format == Format::Gzip && !self.reader.is_zero_padding()?;
+                    self.state = if more { State::Member } else { State::Done };
+                    self.last = false;
+                }
+                State::Done => return Ok(None),
+            }
+        }
+    }
+}
+
+impl<R: Read> Iterator for Inflater<R> {
+    type Item = Result<Lz77>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.next_code() {
+            Ok(Some(code)) => self.emit(code),
+            Ok(None) => None,
+            Err(e) => {
+                self.state = State::Done;
+                Some(Err(e))
+            }
+        }
+    }
+}
@@ -1,59 +1,937 @@
+pub mod binary_tree;
+pub mod checksum;
+pub mod cost;
 pub mod decoder;
+pub mod deflate;
+pub mod dictionary;
 pub mod encoder;
+pub mod grammar;
 pub mod hash;
 pub mod hash_chain;
+pub mod inflate;
 pub mod lz77;
+pub mod match_finder;
+pub mod pack;
+pub mod stream;
+pub mod suffix_array;
 #[cfg(test)]
 mod test;
 pub mod token;
+pub mod token_file;
+pub mod vocabulary;
 
+use std::borrow::Cow;
 use std::io::Cursor;
+use std::ops::Deref;
 
-use decoder::Decoder;
-use encoder::Encoder;
-use numpy::{PyArray1, PyReadonlyArrayDyn};
+use cost::{CostModel, TokenCount, TokenWeights};
+use decoder::{DecodeError, Decoder, Repair};
+use deflate::{compress, Format};
+use dictionary::train_dictionary_with_config;
+use encoder::{Encoder, EncoderConfig, Parsing};
+use grammar::TokenGrammar;
+use inflate::{InflateError, Inflater};
+use match_finder::{MatchFinder, MatchFinderKind};
+use numpy::ndarray::Array2;
+use numpy::{
+    PyArray1, PyArray2, PyReadonlyArray1, PyReadonlyArray2, PyReadonlyArrayDyn, PyUntypedArray,
+};
+use pyo3::exceptions::PyValueError;
 use pyo3::prelude::*;
-use token::{DecoderAdapter, EncoderAdaptor, Token};
+use pyo3::types::{PyByteArray, PyBytes, PyMemoryView, PyString};
+use rayon::prelude::*;
+use stream::{StreamDecoder, StreamEncoder};
+use suffix_array::SuffixArray;
+use token::{DecoderAdapter, EncoderAdaptor, MetaToken, Token, Vocab};
+use vocabulary::{parse_codes, Layout, Vocabulary};
 
-fn encode(xs: &[u8]) -> Vec<u16> {
+/// least max_len of encode_batch with sentence tokens and truncation
+pub const MIN_SENTENCE_LEN: usize = 2;
+
+/// bytes per block of encode_parallel; pigz uses 128k, but blocks here are cut off
+/// from the matches and lookahead of the next one, so fewer but larger is better
+pub const DEFAULT_BLOCK_SIZE: usize = 1 << 20; // 1M
+
+pub fn encode(xs: &[u8]) -> Vec<u16> {
+    encode_with_config(xs, EncoderConfig::default())
+}
+
+pub fn encode_with_config(xs: &[u8], config: EncoderConfig) -> Vec<u16> {
+    encode_with_cost_model(xs, config, TokenCount)
+}
+
+pub fn encode_with_cost_model<C: CostModel>(
+    xs: &[u8],
+    config: EncoderConfig,
+    cost_model: C,
+) -> Vec<u16> {
+    encode_tokens(xs, config, cost_model, &[], false, 0, usize::MAX)
+}
+
+/// with another match finder than config.match_finder, e.g., SuffixArray::new(xs, &config)
+pub fn encode_with_match_finder<M: MatchFinder>(
+    xs: &[u8],
+    config: EncoderConfig,
+    match_finder: M,
+) -> Vec<u16> {
+    let encoder = Encoder::with_match_finder(xs, config, TokenCount, match_finder, false);
+    let vocab = config.vocab();
+    EncoderAdaptor::new(encoder).map(|x| vocab.id(x)).collect()
+}
+
+/// optimal parsing of the exact longest matches within the window, and nearer shorter
+/// ones, found with a suffix array of the whole input; slower than the hash chain
+pub fn encode_offline(xs: &[u8], config: EncoderConfig) -> Vec<u16> {
+    let config = EncoderConfig {
+        parsing: Parsing::Optimal,
+        ..config
+    };
+    encode_with_match_finder(xs, config, SuffixArray::new(xs, &config))
+}
+
+/// matches may reference the preset dictionary, which must be passed to decode_with_dictionary
+pub fn encode_with_dictionary(xs: &[u8], config: EncoderConfig, dictionary: &[u8]) -> Vec<u16> {
+    encode_tokens(xs, config, TokenCount, dictionary, false, 0, usize::MAX)
+}
+
+/// encode blocks of block_size bytes in parallel, like pigz: each one is primed with
+/// the window of bytes before it as its preset dictionary, so matches still reach back
+/// across block boundaries, and the tokens are those of a single stream, which
+/// decode_with_vocab decodes as is. Matches do not run past the end of a block.
+/// block_size must be at least the window, config.max_distance(), as priming hashes a
+/// whole window per block. Besides the input and the tokens, each thread holds an
+/// encoder of two windows of buffer, and at least 64k, plus its match finder's tables
+pub fn encode_parallel(xs: &[u8], config: EncoderConfig, block_size: usize) -> Vec<u16> {
+    let window = config.max_distance();
+    assert!(
+        block_size >= window,
+        "block_size must be at least the window"
+    );
+    let blocks: Vec<Vec<u16>> = xs
+        .par_chunks(block_size)
+        .enumerate()
+        .map(|(i, block)| {
+            let start = i * block_size;
+            let dictionary = &xs[start.saturating_sub(window)..start];
+            encode_with_dictionary(block, config, dictionary)
+        })
+        .collect();
+    blocks.concat()
+}
+
+/// tokens along with the (start, end) byte range of xs that each token covers
+pub fn encode_with_offsets(xs: &[u8], config: EncoderConfig) -> (Vec<u16>, Vec<(usize, usize)>) {
+    let cursor = Cursor::new(xs);
+    let encoder = Encoder::with_config(cursor, config, false);
+    let tokens: Vec<Token> = EncoderAdaptor::new(encoder).collect();
+    let offsets = token::offsets(&tokens);
+    let vocab = config.vocab();
+    (tokens.into_iter().map(|x| vocab.id(x)).collect(), offsets)
+}
+
+/// tokens for the Lz77 codes chosen by the compressor of a DEFLATE stream, without re-encoding
+pub fn encode_deflate(xs: &[u8], format: Format) -> Result<Vec<u16>, InflateError> {
+    let codes = Inflater::new(xs, format).collect::<Result<Vec<_>, _>>()?;
+    Ok(EncoderAdaptor::new(codes.into_iter())
+        .map(u16::from)
+        .collect())
+}
+
+/// encode each document, in parallel, into a row, truncated on symbol boundaries to max_len if given;
+/// rows are padded with Pad up to max_len if pad is set, else up to the longest row.
+/// returns the tokens and the attention mask, which is false for the padding.
+/// panics if truncated sentences cannot fit StartOfSentence and EndOfSentence, see
+/// MIN_SENTENCE_LEN
+pub fn encode_batch(
+    xs: &[&[u8]],
+    config: EncoderConfig,
+    sentence: bool,
+    max_len: Option<usize>,
+    pad: bool,
+    truncation: bool,
+) -> (Array2<u16>, Array2<bool>) {
+    assert!(
+        !(sentence && truncation && max_len.is_some_and(|x| x < MIN_SENTENCE_LEN)),
+        "max_len must fit StartOfSentence and EndOfSentence"
+    );
+    let truncate_to = max_len.filter(|_| truncation).unwrap_or(usize::MAX);
+    let rows: Vec<Vec<u16>> = xs
+        .par_iter()
+        .map(|x| encode_tokens(x, config, TokenCount, &[], sentence, 0, truncate_to))
+        .collect();
+    let longest = rows.iter().map(Vec::len).max().unwrap_or(0);
+    let width = match max_len {
+        Some(max_len) if pad => longest.max(max_len),
+        _ => longest,
+    };
+    let pad_id = config.vocab().id(Token::Meta(MetaToken::Pad));
+    let mut tokens = Array2::from_elem((rows.len(), width), pad_id);
+    let mut mask = Array2::from_elem((rows.len(), width), false);
+    for (i, row) in rows.iter().enumerate() {
+        for (j, &x) in row.iter().enumerate() {
+            tokens[[i, j]] = x;
+            mask[[i, j]] = true;
+        }
+    }
+    (tokens, mask)
+}
+
+/// ids of another layout than Vocab's, see Vocabulary; the window is the vocabulary's
+pub fn encode_with_vocabulary<V: Vocabulary + ?Sized>(
+    xs: &[u8],
+    config: EncoderConfig,
+    vocab: &V,
+) -> Vec<u16> {
+    let config = EncoderConfig {
+        window_bits: vocab.window_bits(),
+        ..config
+    };
+    let mut ids = Vec::with_capacity(xs.len() / 2);
+    for code in Encoder::with_config(Cursor::new(xs), config, false) {
+        vocab.push_ids(code, &mut ids);
+    }
+    ids
+}
+
+/// dictionary: preset dictionary, if not empty; sentence: wrap in StartOfSentence/EndOfSentence;
+/// pad_to: pad with Pad up to this length; truncate_to: stop before exceeding this length
+fn encode_tokens<C: CostModel>(
+    xs: &[u8],
+    config: EncoderConfig,
+    cost_model: C,
+    dictionary: &[u8],
+    sentence: bool,
+    pad_to: usize,
+    truncate_to: usize,
+) -> Vec<u16> {
     let cursor = Cursor::new(xs);
-    let encoder = Encoder::new(cursor, false);
-    let adaptor = EncoderAdaptor::new(encoder);
-    adaptor.map(u16::from).collect()
+    let encoder =
+        Encoder::with_cost_model(cursor, config, cost_model, false).with_dictionary(dictionary);
+    let mut adaptor = EncoderAdaptor::new(encoder)
+        .with_padding(pad_to)
+        .with_truncation(truncate_to);
+    if sentence {
+        adaptor = adaptor.with_sentence_tokens();
+    }
+    let vocab = config.vocab();
+    adaptor.map(|x| vocab.id(x)).collect()
+}
+
+pub fn decode(xs: &[u16]) -> Result<Vec<u8>, DecodeError> {
+    decode_with_dictionary(xs, &[])
 }
 
-fn decode(xs: &[u16]) -> Vec<u8> {
-    let adaptor = DecoderAdapter::new(xs.iter().map(|x| Token::from(*x)));
-    let decoder = Decoder::new(adaptor);
+/// decode tokens from encode_with_dictionary with the same dictionary
+pub fn decode_with_dictionary(xs: &[u16], dictionary: &[u8]) -> Result<Vec<u8>, DecodeError> {
+    decode_with_vocab(xs, Vocab::default(), dictionary)
+}
+
+/// decode tokens of the vocabulary, i.e., of the window, that EncoderConfig::vocab gives,
+/// with the preset dictionary, if not empty
+pub fn decode_with_vocab(
+    xs: &[u16],
+    vocab: Vocab,
+    dictionary: &[u8],
+) -> Result<Vec<u8>, DecodeError> {
+    let tokens = xs
+        .iter()
+        .enumerate()
+        .map(|(position, &id)| {
+            vocab
+                .token(id)
+                .ok_or(DecodeError::InvalidToken { position, id })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let adaptor = DecoderAdapter::new(tokens.into_iter()).with_dictionary_len(dictionary.len());
+    let decoder = Decoder::new(adaptor)
+        .with_window_bits(vocab.window_bits())
+        .with_dictionary(dictionary);
+    decoder.collect()
+}
+
+/// decode ids from encode_with_vocabulary with the same vocabulary
+pub fn decode_with_vocabulary<V: Vocabulary + ?Sized>(
+    xs: &[u16],
+    vocab: &V,
+) -> Result<Vec<u8>, DecodeError> {
+    let codes = parse_codes(vocab, xs, 0)?;
+    let decoder = Decoder::new(codes.into_iter().map(Ok)).with_window_bits(vocab.window_bits());
     decoder.collect()
 }
 
+/// decode documents in parallel, with the vocabulary of the encoder's window, see
+/// decode_with_vocab
+pub fn decode_batch(xs: &[&[u16]], vocab: Vocab) -> Vec<Result<Vec<u8>, DecodeError>> {
+    xs.par_iter()
+        .map(|x| decode_with_vocab(x, vocab, &[]))
+        .collect()
+}
+
+/// best-effort decoding that repairs invalid tokens rather than failing, with the
+/// vocabulary of the encoder's window, see decode_with_vocab
+pub fn decode_lenient(xs: &[u16], vocab: Vocab) -> (Vec<u8>, Vec<Repair>) {
+    let mut repairs = Vec::new();
+    // positions of the valid ids, as the adapter only sees those
+    let mut positions = Vec::with_capacity(xs.len());
+    let mut tokens = Vec::with_capacity(xs.len());
+    for (position, &id) in xs.iter().enumerate() {
+        match vocab.token(id) {
+            Some(token) => {
+                positions.push(position);
+                tokens.push(token);
+            }
+            None => repairs.push(Repair::InvalidToken { position, id }),
+        }
+    }
+    let mut decoder = Decoder::new(DecoderAdapter::lenient(tokens.into_iter()))
+        .with_window_bits(vocab.window_bits());
+    let result = decoder.by_ref().map(Result::unwrap).collect();
+    for &repair in decoder.get_ref().repairs() {
+        let mut repair = repair;
+        *repair.position_mut() = positions[repair.position()];
+        repairs.push(repair);
+    }
+    repairs.sort_by_key(Repair::position);
+    (result, repairs)
+}
+
+/// tokens and attention mask
+type PyBatch<'py> = (Bound<'py, PyArray2<u16>>, Bound<'py, PyArray2<bool>>);
+
+/// tokens and their (start, end) byte offsets
+type PyOffsets<'py> = (Bound<'py, PyArray1<u16>>, Bound<'py, PyArray2<u64>>);
+
+fn py_config(level: Option<u32>, optimal: bool, window_bits: u32) -> PyResult<EncoderConfig> {
+    let mut config = match level {
+        Some(level) => EncoderConfig::level(level)
+            .ok_or_else(|| PyValueError::new_err("level must be between 0 and 9"))?,
+        None => EncoderConfig::default(),
+    };
+    if optimal {
+        config.parsing = Parsing::Optimal;
+    }
+    config.window_bits = py_vocab(window_bits)?.window_bits();
+    Ok(config)
+}
+
+fn py_match_finder(match_finder: &str) -> PyResult<MatchFinderKind> {
+    MatchFinderKind::parse(match_finder)
+        .ok_or_else(|| PyValueError::new_err("match_finder must be 'hc' or 'bt'"))
+}
+
+fn py_vocab(window_bits: u32) -> PyResult<Vocab> {
+    Vocab::new(window_bits).ok_or_else(|| {
+        PyValueError::new_err(format!(
+            "window_bits must be between {} and {}",
+            lz77::MIN_WINDOW_BITS,
+            lz77::MAX_WINDOW_BITS
+        ))
+    })
+}
+
+fn py_format(format: &str) -> PyResult<Format> {
+    match format {
+        "raw" => Ok(Format::Raw),
+        "zlib" => Ok(Format::Zlib),
+        "gzip" => Ok(Format::Gzip),
+        _ => Err(PyValueError::new_err(
+            "format must be one of 'raw', 'zlib' or 'gzip'",
+        )),
+    }
+}
+
+/// bytes of a Python object, see py_bytes; the GIL is released with a slice of it,
+/// as the object itself must stay with the GIL
+enum PyInput<'py> {
+    /// contiguous numpy array over the memory of a bytes object, borrowed
+    Array(PyReadonlyArrayDyn<'py, u8>),
+    /// bytes, borrowed, or the single copy made by str.encode or memoryview.tobytes
+    Bytes(Bound<'py, PyBytes>),
+    /// bytearray or any other array, which may change once the GIL is released
+    Owned(Vec<u8>),
+}
+
+impl Deref for PyInput<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Self::Array(xs) => xs.as_slice().expect("contiguous array"),
+            Self::Bytes(xs) => xs.as_bytes(),
+            Self::Owned(xs) => xs,
+        }
+    }
+}
+
+/// bytes of a numpy uint8 array of any layout, bytes, bytearray, str (UTF-8 encoded)
+/// or any other object supporting the buffer protocol, e.g., memoryview; bytes and
+/// contiguous arrays over them, e.g., np.frombuffer(bytes), are borrowed, anything
+/// else is copied once
+fn py_bytes<'py>(xs: &Bound<'py, PyAny>) -> PyResult<PyInput<'py>> {
+    if let Ok(xs) = xs.downcast::<PyString>() {
+        return Ok(PyInput::Bytes(xs.encode_utf8()?));
+    }
+    if let Ok(ys) = xs.extract::<PyReadonlyArrayDyn<u8>>() {
+        if ys.as_slice().is_ok() && py_immutable(xs)? {
+            return Ok(PyInput::Array(ys));
+        }
+        return Ok(PyInput::Owned(py_tokens(&ys).into_owned()));
+    }
+    if let Ok(xs) = xs.downcast::<PyBytes>() {
+        return Ok(PyInput::Bytes(xs.clone()));
+    }
+    if let Ok(xs) = xs.downcast::<PyByteArray>() {
+        return Ok(PyInput::Owned(xs.to_vec()));
+    }
+    let xs = PyMemoryView::from_bound(xs)?.call_method0("tobytes")?;
+    Ok(PyInput::Bytes(xs.downcast_into::<PyBytes>()?))
+}
+
+/// whether no Python code can change the array while the GIL is released: it and the
+/// arrays it views are read-only, down to the bytes object that owns the memory; an
+/// array that owns its memory may be made writeable again
+fn py_immutable(xs: &Bound<'_, PyAny>) -> PyResult<bool> {
+    let mut xs = xs.clone();
+    loop {
+        if xs.is_instance_of::<PyBytes>() {
+            return Ok(true);
+        }
+        if !xs.is_instance_of::<PyUntypedArray>()
+            || xs.getattr("flags")?.getattr("writeable")?.extract()?
+        {
+            return Ok(false);
+        }
+        xs = xs.getattr("base")?;
+    }
+}
+
+/// borrow the array if contiguous, otherwise copy it
+fn py_tokens<'a, T: numpy::Element + Copy>(xs: &'a PyReadonlyArrayDyn<'_, T>) -> Cow<'a, [T]> {
+    match xs.as_slice() {
+        Ok(xs) => Cow::Borrowed(xs),
+        Err(_) => Cow::Owned(xs.as_array().iter().copied().collect()),
+    }
+}
+
+/// decoded bytes as a numpy uint8 array, bytes or str
+fn py_output(py: Python<'_>, xs: Vec<u8>, output: &str) -> PyResult<PyObject> {
+    match output {
+        "numpy" => Ok(PyArray1::from_vec_bound(py, xs).into_any().unbind()),
+        "bytes" => Ok(PyBytes::new_bound(py, &xs).into_any().unbind()),
+        "str" => String::from_utf8(xs)
+            .map(|xs| xs.into_py(py))
+            .map_err(|e| PyValueError::new_err(e.to_string())),
+        _ => Err(PyValueError::new_err(
+            "output must be one of 'numpy', 'bytes' or 'str'",
+        )),
+    }
+}
+
+/// Python wrapper of TokenGrammar that produces logits masks
+#[pyclass(name = "TokenGrammar")]
+struct PyTokenGrammar {
+    grammar: TokenGrammar,
+}
+
+#[pymethods]
+impl PyTokenGrammar {
+    #[new]
+    #[pyo3(signature = (window_bits=lz77::DEFAULT_WINDOW_BITS))]
+    fn new(window_bits: u32) -> PyResult<Self> {
+        Ok(Self {
+            grammar: TokenGrammar::with_vocab(py_vocab(window_bits)?),
+        })
+    }
+
+    fn push(&mut self, id: u16) -> PyResult<()> {
+        let result = match self.grammar.vocab().token(id) {
+            Some(token) => self.grammar.push(token),
+            None => Err(DecodeError::InvalidToken {
+                position: self.grammar.position(),
+                id,
+            }),
+        };
+        result.map_err(|e| PyValueError::new_err(e.to_string()))
+    }
+
+    fn extend(&mut self, ids: PyReadonlyArrayDyn<'_, u16>) -> PyResult<()> {
+        for &id in ids.as_array().iter() {
+            self.push(id)?;
+        }
+        Ok(())
+    }
+
+    /// boolean array over the vocabulary, true for the valid next tokens
+    fn mask<'py>(&self, py: Python<'py>) -> Bound<'py, PyArray1<bool>> {
+        let mask = self.grammar.mask();
+        let result = (0..self.grammar.vocab().size())
+            .map(|id| mask[id / 64] >> (id % 64) & 1 == 1)
+            .collect();
+        PyArray1::from_vec_bound(py, result)
+    }
+
+    fn is_complete(&self) -> bool {
+        self.grammar.is_complete()
+    }
+}
+
+/// Python wrapper of StreamEncoder; raises ValueError once finished
+#[pyclass(name = "Encoder")]
+struct PyEncoder {
+    encoder: Option<StreamEncoder>,
+    vocab: Vocab,
+}
+
+#[pymethods]
+impl PyEncoder {
+    #[new]
+    #[pyo3(signature = (level=None, optimal=false, sentence=false, window_bits=lz77::DEFAULT_WINDOW_BITS))]
+    fn new(level: Option<u32>, optimal: bool, sentence: bool, window_bits: u32) -> PyResult<Self> {
+        let config = py_config(level, optimal, window_bits)?;
+        Ok(Self {
+            encoder: Some(StreamEncoder::new(config, sentence)),
+            vocab: config.vocab(),
+        })
+    }
+
+    fn feed<'py>(
+        &mut self,
+        py: Python<'py>,
+        xs: &Bound<'py, PyAny>,
+    ) -> PyResult<Bound<'py, PyArray1<u16>>> {
+        let input = py_bytes(xs)?;
+        let xs: &[u8] = &input;
+        let encoder = self
+            .encoder
+            .as_mut()
+            .ok_or_else(|| PyValueError::new_err("encoder is finished"))?;
+        let tokens = py.allow_threads(|| encoder.feed(xs));
+        let result = tokens.into_iter().map(|x| self.vocab.id(x)).collect();
+        Ok(PyArray1::from_vec_bound(py, result))
+    }
+
+    fn finish<'py>(&mut self, py: Python<'py>) -> PyResult<Bound<'py, PyArray1<u16>>> {
+        let encoder = self
+            .encoder
+            .take()
+            .ok_or_else(|| PyValueError::new_err("encoder is finished"))?;
+        let tokens = py.allow_threads(|| encoder.finish());
+        let result = tokens.into_iter().map(|x| self.vocab.id(x)).collect();
+        Ok(PyArray1::from_vec_bound(py, result))
+    }
+}
+
+/// Python wrapper of StreamDecoder; raises ValueError on invalid tokens or once finished
+#[pyclass(name = "Decoder")]
+struct PyDecoder {
+    decoder: Option<StreamDecoder>,
+    vocab: Vocab,
+}
+
+#[pymethods]
+impl PyDecoder {
+    #[new]
+    #[pyo3(signature = (window_bits=lz77::DEFAULT_WINDOW_BITS))]
+    fn new(window_bits: u32) -> PyResult<Self> {
+        let vocab = py_vocab(window_bits)?;
+        Ok(Self {
+            decoder: Some(StreamDecoder::with_vocab(vocab)),
+            vocab,
+        })
+    }
+
+    fn feed<'py>(&mut self, py: Python<'py>, ids: Vec<u16>) -> PyResult<Bound<'py, PyBytes>> {
+        let decoder = self
+            .decoder
+            .as_mut()
+            .ok_or_else(|| PyValueError::new_err("decoder is finished"))?;
+        let position = decoder.position();
+        let tokens = ids
+            .iter()
+            .enumerate()
+            .map(|(i, &id)| {
+                self.vocab.token(id).ok_or_else(|| {
+                    let e = DecodeError::InvalidToken {
+                        position: position + i,
+                        id,
+                    };
+                    PyValueError::new_err(e.to_string())
+                })
+            })
+            .collect::<PyResult<Vec<_>>>()?;
+        let result = decoder
+            .feed(&tokens)
+            .map_err(|e| PyValueError::new_err(e.to_string()))?;
+        Ok(PyBytes::new_bound(py, &result))
+    }
+
+    fn finish(&mut self) -> PyResult<()> {
+        let decoder = self
+            .decoder
+            .take()
+            .ok_or_else(|| PyValueError::new_err("decoder is finished"))?;
+        decoder
+            .finish()
+            .map_err(|e| PyValueError::new_err(e.to_string()))
+    }
+}
+
+/// Python wrapper of a Vocabulary, for comparing token layouts:
+/// "bytes" (the default one), "deflate", "joint" or "log"
+#[pyclass(name = "Vocabulary")]
+struct PyVocabulary {
+    vocab: Box<dyn Vocabulary + Send + Sync>,
+}
+
+#[pymethods]
+impl PyVocabulary {
+    #[new]
+    #[pyo3(signature = (layout="bytes", window_bits=lz77::DEFAULT_WINDOW_BITS))]
+    fn new(layout: &str, window_bits: u32) -> PyResult<Self> {
+        let layout = Layout::parse(layout).ok_or_else(|| {
+            PyValueError::new_err("layout must be one of 'bytes', 'deflate', 'joint' or 'log'")
+        })?;
+        let vocab = layout.vocabulary(window_bits).ok_or_else(|| {
+            PyValueError::new_err(format!("unsupported window_bits {}", window_bits))
+        })?;
+        Ok(Self { vocab })
+    }
+
+    #[getter]
+    fn size(&self) -> usize {
+        self.vocab.vocab_size()
+    }
+
+    #[pyo3(signature = (xs, level=None, optimal=false))]
+    fn encode<'py>(
+        &self,
+        py: Python<'py>,
+        xs: &Bound<'py, PyAny>,
+        level: Option<u32>,
+        optimal: bool,
+    ) -> PyResult<Bound<'py, PyArray1<u16>>> {
+        let config = py_config(level, optimal, lz77::DEFAULT_WINDOW_BITS)?;
+        let input = py_bytes(xs)?;
+        let xs: &[u8] = &input;
+        let result = py.allow_threads(|| encode_with_vocabulary(xs, config, self.vocab.as_ref()));
+        Ok(PyArray1::from_vec_bound(py, result))
+    }
+
+    #[pyo3(signature = (xs, output="numpy"))]
+    fn decode<'py>(
+        &self,
+        py: Python<'py>,
+        xs: PyReadonlyArrayDyn<'py, u16>,
+        output: &str,
+    ) -> PyResult<PyObject> {
+        let xs = py_tokens(&xs);
+        let result = py
+            .allow_threads(|| decode_with_vocabulary(&xs, self.vocab.as_ref()))
+            .map_err(|e| PyValueError::new_err(e.to_string()))?;
+        py_output(py, result, output)
+    }
+}
+
 /// A Python module implemented in Rust. The name of this function must match
 /// the `lib.name` setting in the `Cargo.toml`, else Python will not be able to
 /// import the module.
 #[pymodule]
 fn lz(m: &Bound<'_, PyModule>) -> PyResult<()> {
     #[pyfn(m)]
-    #[pyo3(name = "encode")]
+    #[pyo3(
+        name = "encode",
+        signature = (xs, level=None, optimal=false, weights=None, sentence=false, pad_to=0, dictionary=None, window_bits=lz77::DEFAULT_WINDOW_BITS, match_finder="hc")
+    )]
+    #[allow(clippy::too_many_arguments)]
     fn encode_py<'py>(
         py: Python<'py>,
-        xs: PyReadonlyArrayDyn<'py, u8>,
-    ) -> Bound<'py, PyArray1<u16>> {
-        let result = encode(xs.as_slice().unwrap());
+        xs: &Bound<'py, PyAny>,
+        level: Option<u32>,
+        optimal: bool,
+        weights: Option<PyReadonlyArray1<'py, f32>>,
+        sentence: bool,
+        pad_to: usize,
+        dictionary: Option<&Bound<'py, PyAny>>,
+        window_bits: u32,
+        match_finder: &str,
+    ) -> PyResult<Bound<'py, PyArray1<u16>>> {
+        let mut config = py_config(level, optimal || weights.is_some(), window_bits)?;
+        config.match_finder = py_match_finder(match_finder)?;
+        let input = py_bytes(xs)?;
+        let xs: &[u8] = &input;
+        let dictionary = dictionary.map(py_bytes).transpose()?;
+        let dictionary: &[u8] = dictionary.as_deref().unwrap_or_default();
+        let weights = match weights {
+            Some(weights) => {
+                let weights = weights.as_slice()?.to_vec();
+                if weights.len() != config.vocab().size() {
+                    return Err(PyValueError::new_err(format!(
+                        "weights must have {} entries",
+                        config.vocab().size()
+                    )));
+                }
+                let weights = TokenWeights::new(weights).ok_or_else(|| {
+                    PyValueError::new_err("weights must be finite and non-negative")
+                })?;
+                Some(weights)
+            }
+            None => None,
+        };
+        let result = py.allow_threads(|| match weights {
+            Some(cost_model) => encode_tokens(
+                xs,
+                config,
+                cost_model,
+                dictionary,
+                sentence,
+                pad_to,
+                usize::MAX,
+            ),
+            None => encode_tokens(
+                xs,
+                config,
+                TokenCount,
+                dictionary,
+                sentence,
+                pad_to,
+                usize::MAX,
+            ),
+        });
         let array = PyArray1::from_vec_bound(py, result);
-        array
+        Ok(array)
+    }
+
+    /// encode with all cores, in blocks that decode as a single stream, see encode_parallel
+    #[pyfn(m)]
+    #[pyo3(
+        name = "encode_parallel",
+        signature = (xs, block_size=DEFAULT_BLOCK_SIZE, level=None, optimal=false, window_bits=lz77::DEFAULT_WINDOW_BITS, match_finder="hc")
+    )]
+    fn encode_parallel_py<'py>(
+        py: Python<'py>,
+        xs: &Bound<'py, PyAny>,
+        block_size: usize,
+        level: Option<u32>,
+        optimal: bool,
+        window_bits: u32,
+        match_finder: &str,
+    ) -> PyResult<Bound<'py, PyArray1<u16>>> {
+        let mut config = py_config(level, optimal, window_bits)?;
+        config.match_finder = py_match_finder(match_finder)?;
+        if block_size < config.max_distance() {
+            return Err(PyValueError::new_err(format!(
+                "block_size must be at least the window, {} bytes",
+                config.max_distance()
+            )));
+        }
+        let input = py_bytes(xs)?;
+        let xs: &[u8] = &input;
+        let result = py.allow_threads(|| encode_parallel(xs, config, block_size));
+        Ok(PyArray1::from_vec_bound(py, result))
+    }
+
+    /// returns the tokens and an (n, 2) array of their (start, end) byte offsets
+    #[pyfn(m)]
+    #[pyo3(
+        name = "encode_with_offsets",
+        signature = (xs, level=None, optimal=false, window_bits=lz77::DEFAULT_WINDOW_BITS)
+    )]
+    fn encode_with_offsets_py<'py>(
+        py: Python<'py>,
+        xs: &Bound<'py, PyAny>,
+        level: Option<u32>,
+        optimal: bool,
+        window_bits: u32,
+    ) -> PyResult<PyOffsets<'py>> {
+        let config = py_config(level, optimal, window_bits)?;
+        let input = py_bytes(xs)?;
+        let xs: &[u8] = &input;
+        let (tokens, offsets) = py.allow_threads(|| encode_with_offsets(xs, config));
+        let offsets = Array2::from_shape_fn((offsets.len(), 2), |(i, j)| match j {
+            0 => offsets[i].0 as u64,
+            _ => offsets[i].1 as u64,
+        });
+        Ok((
+            PyArray1::from_vec_bound(py, tokens),
+            PyArray2::from_owned_array_bound(py, offsets),
+        ))
+    }
+
+    #[pyfn(m)]
+    #[pyo3(
+        name = "encode_batch",
+        signature = (xs, max_len=None, pad=true, truncation=true, level=None, optimal=false, sentence=false, window_bits=lz77::DEFAULT_WINDOW_BITS)
+    )]
+    #[allow(clippy::too_many_arguments)]
+    fn encode_batch_py<'py>(
+        py: Python<'py>,
+        xs: Vec<Bound<'py, PyAny>>,
+        max_len: Option<usize>,
+        pad: bool,
+        truncation: bool,
+        level: Option<u32>,
+        optimal: bool,
+        sentence: bool,
+        window_bits: u32,
+    ) -> PyResult<PyBatch<'py>> {
+        let config = py_config(level, optimal, window_bits)?;
+        if sentence && truncation && max_len.is_some_and(|x| x < MIN_SENTENCE_LEN) {
+            return Err(PyValueError::new_err(format!(
+                "max_len must be at least {} with sentence tokens",
+                MIN_SENTENCE_LEN
+            )));
+        }
+        let inputs = xs.iter().map(py_bytes).collect::<PyResult<Vec<_>>>()?;
+        let xs: Vec<&[u8]> = inputs.iter().map(|x| &x[..]).collect();
+        let (tokens, mask) =
+            py.allow_threads(|| encode_batch(&xs, config, sentence, max_len, pad, truncation));
+        Ok((
+            PyArray2::from_owned_array_bound(py, tokens),
+            PyArray2::from_owned_array_bound(py, mask),
+        ))
+    }
+
+    /// DEFLATE bitstream with format "raw", "zlib" or "gzip" framing
+    #[pyfn(m)]
+    #[pyo3(name = "compress", signature = (xs, format="gzip", level=None, optimal=false))]
+    fn compress_py<'py>(
+        py: Python<'py>,
+        xs: &Bound<'py, PyAny>,
+        format: &str,
+        level: Option<u32>,
+        optimal: bool,
+    ) -> PyResult<Bound<'py, PyBytes>> {
+        let config = py_config(level, optimal, lz77::DEFAULT_WINDOW_BITS)?;
+        let format = py_format(format)?;
+        let input = py_bytes(xs)?;
+        let xs: &[u8] = &input;
+        let result = py.allow_threads(|| compress(xs, Vec::new(), config, format))?;
+        Ok(PyBytes::new_bound(py, &result))
     }
 
+    /// preset dictionary of at most size bytes for encode/decode, trained on the samples
     #[pyfn(m)]
-    #[pyo3(name = "decode")]
+    #[pyo3(
+        name = "train_dictionary",
+        signature = (samples, size, level=None, optimal=false, window_bits=lz77::DEFAULT_WINDOW_BITS)
+    )]
+    fn train_dictionary_py<'py>(
+        py: Python<'py>,
+        samples: Vec<Bound<'py, PyAny>>,
+        size: usize,
+        level: Option<u32>,
+        optimal: bool,
+        window_bits: u32,
+    ) -> PyResult<Bound<'py, PyBytes>> {
+        let config = py_config(level, optimal, window_bits)?;
+        let inputs = samples.iter().map(py_bytes).collect::<PyResult<Vec<_>>>()?;
+        let samples: Vec<&[u8]> = inputs.iter().map(|x| &x[..]).collect();
+        let result = py.allow_threads(|| train_dictionary_with_config(&samples, size, config));
+        Ok(PyBytes::new_bound(py, &result))
+    }
+
+    /// tokens for the matches already chosen in a "raw", "zlib" or "gzip" DEFLATE stream
+    #[pyfn(m)]
+    #[pyo3(name = "encode_deflate", signature = (xs, format="gzip"))]
+    fn encode_deflate_py<'py>(
+        py: Python<'py>,
+        xs: &Bound<'py, PyAny>,
+        format: &str,
+    ) -> PyResult<Bound<'py, PyArray1<u16>>> {
+        let format = py_format(format)?;
+        let input = py_bytes(xs)?;
+        let xs: &[u8] = &input;
+        let tokens = py
+            .allow_threads(|| encode_deflate(xs, format))
+            .map_err(|e| PyValueError::new_err(e.to_string()))?;
+        Ok(PyArray1::from_vec_bound(py, tokens))
+    }
+
+    #[pyfn(m)]
+    #[pyo3(
+        name = "decode",
+        signature = (xs, output="numpy", dictionary=None, window_bits=lz77::DEFAULT_WINDOW_BITS)
+    )]
     fn decode_py<'py>(
         py: Python<'py>,
         xs: PyReadonlyArrayDyn<'py, u16>,
-    ) -> Bound<'py, PyArray1<u8>> {
-        let result = decode(xs.as_slice().unwrap());
-        let array = PyArray1::from_vec_bound(py, result);
-        array
+        output: &str,
+        dictionary: Option<&Bound<'py, PyAny>>,
+        window_bits: u32,
+    ) -> PyResult<PyObject> {
+        let xs = py_tokens(&xs);
+        let vocab = py_vocab(window_bits)?;
+        let dictionary = dictionary.map(py_bytes).transpose()?;
+        let dictionary: &[u8] = dictionary.as_deref().unwrap_or_default();
+        let result = py
+            .allow_threads(|| decode_with_vocab(&xs, vocab, dictionary))
+            .map_err(|e| PyValueError::new_err(e.to_string()))?;
+        py_output(py, result, output)
+    }
+
+    /// decode each row of a 2D array; Pad tokens are skipped, so encode_batch output decodes as is
+    #[pyfn(m)]
+    #[pyo3(
+        name = "decode_batch",
+        signature = (xs, output="numpy", window_bits=lz77::DEFAULT_WINDOW_BITS)
+    )]
+    fn decode_batch_py<'py>(
+        py: Python<'py>,
+        xs: PyReadonlyArray2<'py, u16>,
+        output: &str,
+        window_bits: u32,
+    ) -> PyResult<Vec<PyObject>> {
+        let vocab = py_vocab(window_bits)?;
+        let rows: Vec<Vec<u16>> = xs
+            .as_array()
+            .rows()
+            .into_iter()
+            .map(|row| row.to_vec())
+            .collect();
+        let rows: Vec<&[u16]> = rows.iter().map(Vec::as_slice).collect();
+        let results = py.allow_threads(|| decode_batch(&rows, vocab));
+        results
+            .into_iter()
+            .enumerate()
+            .map(|(i, result)| match result {
+                Ok(result) => py_output(py, result, output),
+                Err(e) => Err(PyValueError::new_err(format!("row {}: {}", i, e))),
+            })
+            .collect()
     }
 
+    #[pyfn(m)]
+    #[pyo3(
+        name = "decode_lenient",
+        signature = (xs, output="numpy", window_bits=lz77::DEFAULT_WINDOW_BITS)
+    )]
+    fn decode_lenient_py<'py>(
+        py: Python<'py>,
+        xs: PyReadonlyArrayDyn<'py, u16>,
+        output: &str,
+        window_bits: u32,
+    ) -> PyResult<(PyObject, Vec<(usize, String)>)> {
+        let vocab = py_vocab(window_bits)?;
+        let xs = py_tokens(&xs);
+        let (result, repairs) = py.allow_threads(|| decode_lenient(&xs, vocab));
+        let repairs = repairs
+            .into_iter()
+            .map(|repair| (repair.position(), repair.to_string()))
+            .collect();
+        Ok((py_output(py, result, output)?, repairs))
+    }
+
+    /// number of token ids for a window of 2^window_bits bytes
+    #[pyfn(m)]
+    #[pyo3(name = "vocab_size", signature = (window_bits=lz77::DEFAULT_WINDOW_BITS))]
+    fn vocab_size_py(window_bits: u32) -> PyResult<usize> {
+        Ok(py_vocab(window_bits)?.size())
+    }
+
+    m.add_class::<PyTokenGrammar>()?;
+    m.add_class::<PyEncoder>()?;
+    m.add_class::<PyDecoder>()?;
+    m.add_class::<PyVocabulary>()?;
+
     Ok(())
 }
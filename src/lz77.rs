@@ -1,4 +1,9 @@
-pub const MAX_DISTANCE: usize = 1 << 15; // 32k
+/// the window can be 2^8 ~ 2^20 bytes, 32k by default as in DEFLATE
+pub const MIN_WINDOW_BITS: u32 = 8;
+pub const MAX_WINDOW_BITS: u32 = 20; // 1M
+pub const DEFAULT_WINDOW_BITS: u32 = 15;
+pub const MAX_DISTANCE: usize = 1 << DEFAULT_WINDOW_BITS; // 32k
+pub const MIN_LENGTH: usize = 3;
 pub const MAX_LENGTH: usize = 258;
 
 #[derive(Clone, Copy, Eq, PartialEq, Debug)]
@@ -6,7 +11,7 @@ pub enum Lz77 {
     Literal(u8),
     Dictionary {
         length: u8, // 0~255 <-> 3~258
-        distance: u16,
+        distance: u32,
     },
 }
 
@@ -0,0 +1,217 @@
+use crate::binary_tree::BinaryTree;
+use crate::encoder::EncoderConfig;
+use crate::hash::RunningHasher;
+use crate::hash_chain::HashChain;
+use crate::lz77::MAX_LENGTH;
+
+/// Indexes the positions of the encoder's ring buffer and finds matches for them.
+/// Positions are absolute and added in order, each exactly once, by either skip or
+/// find; buf is the whole ring buffer, a power of two long, and holds valid bytes
+/// from pos - max_distance up to cap
+pub trait MatchFinder {
+    /// number of bytes from a position that indexing it reads, so the last
+    /// hash_len - 1 positions of a preset dictionary wait for the input
+    fn hash_len(&self) -> usize;
+
+    /// add pos without searching
+    fn skip(&mut self, buf: &[u8], pos: usize, cap: usize);
+
+    /// add pos and call on_match for every (length, distance) longer than best_length
+    /// and any before it; lengths are strictly increasing and so are distances.
+    /// returns the longest, or (best_length, 0) if none
+    fn find<F: FnMut(usize, usize)>(
+        &mut self,
+        buf: &[u8],
+        pos: usize,
+        cap: usize,
+        best_length: usize,
+        max_count: usize,
+        on_match: F,
+    ) -> (usize, usize);
+}
+
+/// the match finder that Encoder::with_config builds, see EncoderConfig::match_finder
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub enum MatchFinderKind {
+    /// HashChainFinder, like zlib
+    #[default]
+    HashChain,
+    /// BinaryTree, like LZMA's bt4: longer matches for the same max_chain, but slower
+    BinaryTree,
+}
+
+impl MatchFinderKind {
+    /// xz's names, without the hash length: "hc" or "bt"
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "hc" => Some(Self::HashChain),
+            "bt" => Some(Self::BinaryTree),
+            _ => None,
+        }
+    }
+}
+
+/// either match finder, chosen at run time by EncoderConfig::match_finder
+pub enum AnyMatchFinder {
+    HashChain(HashChainFinder),
+    BinaryTree(BinaryTree),
+}
+
+impl AnyMatchFinder {
+    pub fn new(config: &EncoderConfig) -> Self {
+        match config.match_finder {
+            MatchFinderKind::HashChain => Self::HashChain(HashChainFinder::new(config)),
+            MatchFinderKind::BinaryTree => Self::BinaryTree(BinaryTree::new(config)),
+        }
+    }
+}
+
+impl MatchFinder for AnyMatchFinder {
+    fn hash_len(&self) -> usize {
+        match self {
+            Self::HashChain(finder) => finder.hash_len(),
+            Self::BinaryTree(finder) => finder.hash_len(),
+        }
+    }
+
+    fn skip(&mut self, buf: &[u8], pos: usize, cap: usize) {
+        match self {
+            Self::HashChain(finder) => finder.skip(buf, pos, cap),
+            Self::BinaryTree(finder) => finder.skip(buf, pos, cap),
+        }
+    }
+
+    fn find<F: FnMut(usize, usize)>(
+        &mut self,
+        buf: &[u8],
+        pos: usize,
+        cap: usize,
+        best_length: usize,
+        max_count: usize,
+        on_match: F,
+    ) -> (usize, usize) {
+        match self {
+            Self::HashChain(finder) => finder.find(buf, pos, cap, best_length, max_count, on_match),
+            Self::BinaryTree(finder) => {
+                finder.find(buf, pos, cap, best_length, max_count, on_match)
+            }
+        }
+    }
+}
+
+/// zlib-style match finder: positions with the same hash of their first hash_len bytes
+/// are chained from the most recent one, and a search visits at most max_count of them.
+/// Entries are absolute positions, modulo 2^32, so indexing a position takes constant
+/// time however the buffer wraps around; those that fall out of the window end a search
+pub struct HashChainFinder {
+    hasher: RunningHasher,
+    hash_len: usize,
+    hash_chain: HashChain,
+    /// hash of each position in the window, as wide as MAX_HASH_BITS, to check chains
+    pos2hash: Vec<u32>,
+    chain_mask: usize,
+    max_distance: usize,
+    nice_length: usize,
+    primed: bool,
+}
+
+impl HashChainFinder {
+    pub fn new(config: &EncoderConfig) -> Self {
+        let window = config.max_distance();
+        Self {
+            hasher: RunningHasher::with_params(
+                config.hash_function,
+                config.hash_len,
+                config.hash_bits,
+            ),
+            hash_len: config.hash_len,
+            hash_chain: HashChain::with_sizes(1 << config.hash_bits, window),
+            pos2hash: vec![0; window],
+            chain_mask: window - 1,
+            max_distance: window,
+            nice_length: config.nice_length,
+            primed: false,
+        }
+    }
+
+    /// update the hash and return the most recent position that has hash clash
+    fn insert(&mut self, buf: &[u8], pos: usize) -> u32 {
+        let buf_mask = buf.len() - 1;
+        if !self.primed {
+            self.primed = true;
+            for i in 0..self.hash_len - 1 {
+                self.hasher.update(buf[(pos + i) & buf_mask]);
+            }
+        }
+
+        let hash_pos = pos + self.hash_len - 1;
+        let h = self.hasher.update(buf[hash_pos & buf_mask]);
+        self.pos2hash[pos & self.chain_mask] = h as u32;
+        self.hash_chain.add(h, pos as u32)
+    }
+}
+
+impl MatchFinder for HashChainFinder {
+    fn hash_len(&self) -> usize {
+        self.hash_len
+    }
+
+    fn skip(&mut self, buf: &[u8], pos: usize, _cap: usize) {
+        self.insert(buf, pos);
+    }
+
+    fn find<F: FnMut(usize, usize)>(
+        &mut self,
+        buf: &[u8],
+        search_pos: usize,
+        cap: usize,
+        mut best_length: usize,
+        mut max_count: usize,
+        mut on_match: F,
+    ) -> (usize, usize) {
+        let mut pos = self.insert(buf, search_pos);
+        let mut best_distance = 0;
+        let mut prev_distance = 0;
+        let upper_bound = MAX_LENGTH.min(cap - search_pos);
+        let nice_length = self.nice_length.min(upper_bound);
+
+        while max_count > 0 && pos != 0 && best_length < nice_length {
+            // a reused entry of a position out of the window may point forward
+            let distance = (search_pos as u32).wrapping_sub(pos) as usize;
+            if prev_distance >= distance || distance > self.max_distance {
+                break;
+            }
+            let target = search_pos - distance;
+
+            debug_assert_eq!(
+                self.pos2hash[target & self.chain_mask],
+                self.hasher.get() as u32
+            );
+
+            let buf_mask = buf.len() - 1;
+            if buf[(search_pos + best_length) & buf_mask] == buf[(target + best_length) & buf_mask]
+            {
+                let length = match_length(buf, search_pos, target, upper_bound);
+                if length > best_length {
+                    best_length = length;
+                    best_distance = distance;
+                    on_match(length, distance);
+                }
+            }
+
+            pos = self.hash_chain.get(pos);
+            prev_distance = distance;
+            max_count -= 1;
+        }
+
+        (best_length, best_distance)
+    }
+}
+
+/// length of the common prefix of the bytes at pos and target, up to max_length
+pub(crate) fn match_length(buf: &[u8], pos: usize, target: usize, max_length: usize) -> usize {
+    let buf_mask = buf.len() - 1;
+    (0..max_length)
+        .take_while(|&i| buf[(pos + i) & buf_mask] == buf[(target + i) & buf_mask])
+        .count()
+}
@@ -0,0 +1,211 @@
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Read, Result, Write};
+use std::path::{Path, PathBuf};
+
+use rayon::prelude::*;
+
+use crate::encoder::{Encoder, EncoderConfig};
+use crate::token::{EncoderAdaptor, MetaToken, Token, Vocab};
+
+pub const INDEX_MAGIC: [u8; 4] = *b"LZIX";
+pub const INDEX_VERSION: u16 = 1;
+pub const DEFAULT_SHARD_SIZE: usize = 100_000_000;
+
+/// Splits a stream of documents, separated by EndOfSentence, into shards of exactly
+/// shard_size tokens (save the last one); documents may continue into the next shard.
+/// Each shard is a raw little-endian u16 array `{prefix}_{n:06}.bin`, memory-mappable as
+/// is, next to `{prefix}_{n:06}.idx`: magic (4 bytes), version (u16), reserved (u16),
+/// vocabulary size (u32), number of documents (u64), followed by the u64 offset in the
+/// shard of each document starting in it
+pub struct ShardWriter {
+    prefix: PathBuf,
+    shard_size: usize,
+    tokens: Vec<u16>,
+    starts: Vec<u64>,
+    documents: usize,
+    shards: Vec<PathBuf>,
+    vocab: Vocab,
+}
+
+impl ShardWriter {
+    pub fn new<P: AsRef<Path>>(prefix: P, shard_size: usize) -> Self {
+        assert!(shard_size > 0);
+        Self {
+            prefix: prefix.as_ref().to_path_buf(),
+            shard_size,
+            tokens: Vec::new(),
+            starts: Vec::new(),
+            documents: 0,
+            shards: Vec::new(),
+            vocab: Vocab::default(),
+        }
+    }
+
+    /// for ids of a vocabulary other than the default one
+    pub fn with_vocab(mut self, vocab: Vocab) -> Self {
+        self.vocab = vocab;
+        self
+    }
+
+    /// number of documents pushed so far
+    pub fn documents(&self) -> usize {
+        self.documents
+    }
+
+    pub fn push(&mut self, document: &[u16]) -> Result<()> {
+        if self.documents > 0 {
+            let eos = self.vocab.id(Token::Meta(MetaToken::EndOfSentence));
+            self.extend(&[eos])?;
+            // the document starts in the next shard
+            if self.tokens.len() == self.shard_size {
+                self.flush()?;
+            }
+        }
+        self.starts.push(self.tokens.len() as u64);
+        self.documents += 1;
+        self.extend(document)
+    }
+
+    fn extend(&mut self, mut xs: &[u16]) -> Result<()> {
+        loop {
+            if self.tokens.len() == self.shard_size {
+                self.flush()?;
+            }
+            let n = xs.len().min(self.shard_size - self.tokens.len());
+            self.tokens.extend_from_slice(&xs[..n]);
+            xs = &xs[n..];
+            if xs.is_empty() {
+                return Ok(());
+            }
+        }
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        let path = |extension| {
+            let mut name = self.prefix.file_name().unwrap_or_default().to_os_string();
+            name.push(format!("_{:06}.{}", self.shards.len(), extension));
+            self.prefix.with_file_name(name)
+        };
+        let (bin, idx) = (path("bin"), path("idx"));
+
+        let mut writer = BufWriter::new(File::create(&bin)?);
+        for x in &self.tokens {
+            writer.write_all(&x.to_le_bytes())?;
+        }
+        writer.flush()?;
+
+        let mut writer = BufWriter::new(File::create(idx)?);
+        writer.write_all(&INDEX_MAGIC)?;
+        writer.write_all(&INDEX_VERSION.to_le_bytes())?;
+        writer.write_all(&0u16.to_le_bytes())?;
+        writer.write_all(&(self.vocab.size() as u32).to_le_bytes())?;
+        writer.write_all(&(self.starts.len() as u64).to_le_bytes())?;
+        for start in &self.starts {
+            writer.write_all(&start.to_le_bytes())?;
+        }
+        writer.flush()?;
+
+        self.tokens.clear();
+        self.starts.clear();
+        self.shards.push(bin);
+        Ok(())
+    }
+
+    /// writes the last shard, returns the paths of the .bin files
+    pub fn finish(mut self) -> Result<Vec<PathBuf>> {
+        // an empty last document may start a shard of its own
+        if !self.tokens.is_empty() || !self.starts.is_empty() || self.shards.is_empty() {
+            self.flush()?;
+        }
+        Ok(self.shards)
+    }
+}
+
+/// tokens and document offsets of a shard written by ShardWriter, given its .bin path
+pub fn read_shard<P: AsRef<Path>>(path: P) -> Result<(Vec<u16>, Vec<u64>)> {
+    let path = path.as_ref();
+    let bytes = fs::read(path)?;
+    let tokens = bytes
+        .chunks_exact(2)
+        .map(|x| u16::from_le_bytes([x[0], x[1]]))
+        .collect();
+    let bytes = fs::read(path.with_extension("idx"))?;
+    let invalid = || Error::new(ErrorKind::InvalidData, "invalid index");
+    if bytes.len() < 20 || bytes[..4] != INDEX_MAGIC {
+        return Err(invalid());
+    }
+    let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+    let vocab_size = u32::from_le_bytes(bytes[8..12].try_into().unwrap());
+    let n = u64::from_le_bytes(bytes[12..20].try_into().unwrap());
+    if version != INDEX_VERSION || Vocab::from_size(vocab_size as usize).is_none() {
+        return Err(invalid());
+    }
+    if (bytes.len() - 20) as u64 != 8 * n {
+        return Err(invalid());
+    }
+    let starts = bytes[20..]
+        .chunks_exact(8)
+        .map(|x| u64::from_le_bytes(x.try_into().unwrap()))
+        .collect();
+    Ok((tokens, starts))
+}
+
+/// regular files under path, recursively, in a deterministic order, or path itself if
+/// it is not a directory, e.g., a named pipe. Symlinks to files are followed, those to
+/// directories are not, so a cycle of links cannot recurse forever
+pub fn list_files<P: AsRef<Path>>(path: P) -> Result<Vec<PathBuf>> {
+    let path = path.as_ref();
+    if !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let mut files = Vec::new();
+    list_dir(path, &mut files)?;
+    Ok(files)
+}
+
+fn list_dir(path: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    let mut entries = fs::read_dir(path)?
+        .map(|entry| Ok(entry?.path()))
+        .collect::<Result<Vec<_>>>()?;
+    entries.sort();
+    for entry in entries {
+        let file_type = fs::symlink_metadata(&entry)?.file_type();
+        if file_type.is_dir() {
+            list_dir(&entry, files)?;
+        } else if file_type.is_file() || file_type.is_symlink() && entry.is_file() {
+            files.push(entry);
+        }
+    }
+    Ok(())
+}
+
+fn encode_file(path: &Path, config: EncoderConfig) -> Result<Vec<u16>> {
+    let mut xs = Vec::new();
+    BufReader::new(File::open(path)?).read_to_end(&mut xs)?;
+    let encoder = Encoder::with_config(xs.as_slice(), config, false);
+    let vocab = config.vocab();
+    Ok(EncoderAdaptor::new(encoder).map(|x| vocab.id(x)).collect())
+}
+
+/// encode each file as a document, in parallel, into shards of shard_size tokens;
+/// returns the paths of the .bin files
+pub fn pack<P: AsRef<Path>>(
+    paths: &[PathBuf],
+    prefix: P,
+    shard_size: usize,
+    config: EncoderConfig,
+) -> Result<Vec<PathBuf>> {
+    let mut writer = ShardWriter::new(prefix, shard_size).with_vocab(config.vocab());
+    // bounds the number of encoded documents held in memory
+    let batch_size = 16 * rayon::current_num_threads();
+    for paths in paths.chunks(batch_size) {
+        let documents = paths
+            .par_iter()
+            .map(|path| encode_file(path, config))
+            .collect::<Result<Vec<_>>>()?;
+        for document in documents {
+            writer.push(&document)?;
+        }
+    }
+    writer.finish()
+}
@@ -0,0 +1,138 @@
+use std::collections::VecDeque;
+
+use crate::decoder::{DecodeError, Decoder};
+use crate::encoder::{Encoder, EncoderConfig, MIN_READ_AHEAD};
+use crate::grammar::TokenGrammar;
+use crate::token::{DecoderAdapter, EncoderAdaptor, Token, Vocab};
+
+/// Iterator over the items queued so far; it can be refilled after running dry
+pub struct Feed<T> {
+    queue: VecDeque<T>,
+}
+
+impl<T> Default for Feed<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Feed<T> {
+    pub fn new() -> Self {
+        Self {
+            queue: VecDeque::new(),
+        }
+    }
+
+    pub fn push(&mut self, x: T) {
+        self.queue.push_back(x);
+    }
+}
+
+impl<T> Iterator for Feed<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.queue.pop_front()
+    }
+}
+
+/// Push-based encoder; input is held back until there is enough lookahead,
+/// so the tokens are the same as encoding the whole input at once, however it is split
+pub struct StreamEncoder {
+    adaptor: EncoderAdaptor<Encoder<VecDeque<u8>>>,
+}
+
+impl StreamEncoder {
+    pub fn new(config: EncoderConfig, sentence: bool) -> Self {
+        let encoder = Encoder::with_config(VecDeque::new(), config, false);
+        let mut adaptor = EncoderAdaptor::new(encoder);
+        if sentence {
+            adaptor = adaptor.with_sentence_tokens();
+        }
+        Self { adaptor }
+    }
+
+    /// returns the tokens that became available
+    pub fn feed(&mut self, xs: &[u8]) -> Vec<Token> {
+        self.adaptor.get_mut().get_mut().extend(xs);
+        let mut tokens = Vec::new();
+        while self.adaptor.get_mut().get_mut().len() >= MIN_READ_AHEAD {
+            match self.adaptor.next() {
+                Some(token) => tokens.push(token),
+                None => break,
+            }
+        }
+        tokens
+    }
+
+    /// returns the remaining tokens
+    pub fn finish(mut self) -> Vec<Token> {
+        self.adaptor.by_ref().collect()
+    }
+}
+
+/// Push-based decoder; bytes are released as soon as each symbol is complete
+pub struct StreamDecoder {
+    decoder: Decoder<DecoderAdapter<Feed<Token>>>,
+    grammar: TokenGrammar,
+    pending: Vec<Token>,
+}
+
+impl Default for StreamDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StreamDecoder {
+    pub fn new() -> Self {
+        Self::with_vocab(Vocab::default())
+    }
+
+    /// for tokens encoded with the window of this vocabulary
+    pub fn with_vocab(vocab: Vocab) -> Self {
+        let decoder =
+            Decoder::new(DecoderAdapter::new(Feed::new())).with_window_bits(vocab.window_bits());
+        Self {
+            decoder,
+            grammar: TokenGrammar::with_vocab(vocab),
+            pending: Vec::new(),
+        }
+    }
+
+    /// number of tokens fed so far
+    pub fn position(&self) -> usize {
+        self.grammar.position()
+    }
+
+    /// returns the bytes that became available; all tokens are checked before any is
+    /// queued, so on an error none of them is taken and no bytes are held back
+    pub fn feed(&mut self, tokens: &[Token]) -> Result<Vec<u8>, DecodeError> {
+        let mut grammar = self.grammar.clone();
+        for &token in tokens {
+            grammar.push(token)?;
+        }
+        for &token in tokens {
+            self.grammar.push(token).expect("checked above");
+            self.pending.push(token);
+            if self.grammar.is_complete() {
+                let feed = self.decoder.get_mut().get_mut();
+                for token in self.pending.drain(..) {
+                    feed.push(token);
+                }
+            }
+        }
+        self.decoder.by_ref().collect()
+    }
+
+    /// fails if the tokens end in the middle of a Length/Distance0/Distance1 triple
+    pub fn finish(self) -> Result<(), DecodeError> {
+        if self.grammar.is_complete() {
+            Ok(())
+        } else {
+            Err(DecodeError::UnexpectedEnd {
+                position: self.grammar.position(),
+            })
+        }
+    }
+}
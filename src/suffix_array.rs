@@ -0,0 +1,161 @@
+use std::collections::BTreeSet;
+
+use crate::encoder::EncoderConfig;
+use crate::lz77::MAX_LENGTH;
+use crate::match_finder::MatchFinder;
+
+/// rank of each suffix of xs among all of them, sorted by their first MAX_LENGTH bytes
+/// only, as no match is longer; built by prefix doubling, ranking the first 2h bytes
+/// of each suffix by the ranks of its first h bytes and of the h bytes after them
+fn suffix_ranks(xs: &[u8]) -> Vec<u32> {
+    let n = xs.len();
+    let mut rank: Vec<u32> = xs.iter().map(|&x| x as u32).collect();
+    let mut sa: Vec<u32> = (0..n as u32).collect();
+    let mut next = vec![0; n];
+    let mut h = 1;
+    while h < MAX_LENGTH {
+        // 0 for the suffixes that end within h bytes, which go first
+        let key = |i: u32| {
+            let i = i as usize;
+            let second = rank.get(i + h).map_or(0, |&x| x + 1);
+            (rank[i], second)
+        };
+        sa.sort_unstable_by_key(|&i| key(i));
+        for k in 0..n {
+            next[sa[k] as usize] = match k {
+                0 => 0,
+                _ => next[sa[k - 1] as usize] + (key(sa[k - 1]) != key(sa[k])) as u32,
+            };
+        }
+        std::mem::swap(&mut rank, &mut next);
+        h *= 2;
+        // all distinct already
+        if n == 0 || rank[sa[n - 1] as usize] as usize == n - 1 {
+            break;
+        }
+    }
+    rank
+}
+
+/// Offline match finder for an input held in memory: the suffixes of the whole input
+/// are ranked up front, and the positions within the window are kept sorted by rank,
+/// so the longest matches of a position are those of its neighbors in that order,
+/// getting shorter farther away. The longest match within the window is exact,
+/// and nearer but shorter ones are collected from up to max_count neighbors on each
+/// side, which suits Parsing::Optimal. The encoder must read exactly the input
+/// given, without a preset dictionary, or it panics
+pub struct SuffixArray<'a> {
+    xs: &'a [u8],
+    rank: Vec<u32>,
+    /// (rank, position) of the positions within the window
+    window: BTreeSet<(u32, usize)>,
+    max_distance: usize,
+    min_match: usize,
+}
+
+impl<'a> SuffixArray<'a> {
+    pub fn new(xs: &'a [u8], config: &EncoderConfig) -> Self {
+        Self {
+            xs,
+            rank: suffix_ranks(xs),
+            window: BTreeSet::new(),
+            max_distance: config.max_distance(),
+            min_match: config.min_match,
+        }
+    }
+
+    fn match_length(&self, pos: usize, target: usize, max_length: usize) -> usize {
+        let xs = &self.xs[pos..pos + max_length];
+        let ys = &self.xs[target..];
+        xs.iter().zip(ys).take_while(|(x, y)| x == y).count()
+    }
+
+    /// (distance, length) of the positions on one side of pos in rank order, while at
+    /// least min_length long, each nearer than the longer ones before it
+    fn scan<'b>(
+        &self,
+        side: impl Iterator<Item = &'b (u32, usize)>,
+        pos: usize,
+        max_length: usize,
+        min_length: usize,
+        candidates: &mut Vec<(usize, usize)>,
+    ) {
+        let mut nearest = usize::MAX;
+        for &(_, x) in side {
+            let length = self.match_length(pos, x, max_length);
+            if length < min_length {
+                break;
+            }
+            let distance = pos - x;
+            if distance < nearest {
+                nearest = distance;
+                candidates.push((distance, length));
+            }
+        }
+    }
+
+    /// slide the window up to pos, exclusive, and return the key of pos
+    fn slide(&mut self, buf: &[u8], pos: usize) -> (u32, usize) {
+        assert_eq!(
+            buf[pos & (buf.len() - 1)],
+            self.xs[pos],
+            "the encoder must read the input given to SuffixArray::new"
+        );
+        if let Some(old) = pos.checked_sub(self.max_distance + 1) {
+            self.window.remove(&(self.rank[old], old));
+        }
+        (self.rank[pos], pos)
+    }
+}
+
+impl MatchFinder for SuffixArray<'_> {
+    /// ranks are computed up front, from the input rather than the buffer
+    fn hash_len(&self) -> usize {
+        1
+    }
+
+    fn skip(&mut self, buf: &[u8], pos: usize, _cap: usize) {
+        let key = self.slide(buf, pos);
+        self.window.insert(key);
+    }
+
+    fn find<F: FnMut(usize, usize)>(
+        &mut self,
+        buf: &[u8],
+        pos: usize,
+        cap: usize,
+        mut best_length: usize,
+        max_count: usize,
+        mut on_match: F,
+    ) -> (usize, usize) {
+        let key = self.slide(buf, pos);
+        let max_length = MAX_LENGTH.min(cap - pos);
+        let mut candidates = Vec::new();
+        // the match at distance 1 is the nearest, so no neighbor as short is of use,
+        // nor any farther on its side, which keeps runs from scanning max_count each
+        let adjacent = match pos {
+            0 => 0,
+            _ => self.match_length(pos, pos - 1, max_length),
+        };
+        if adjacent >= self.min_match {
+            candidates.push((1, adjacent));
+        }
+        let min_length = self.min_match.max(adjacent + 1);
+        let below = self.window.range(..key).rev().take(max_count);
+        self.scan(below, pos, max_length, min_length, &mut candidates);
+        let above = self.window.range(key..).take(max_count);
+        self.scan(above, pos, max_length, min_length, &mut candidates);
+        self.window.insert(key);
+
+        candidates.sort_unstable();
+        let mut best_distance = 0;
+        for (distance, length) in candidates {
+            if length > best_length {
+                best_length = length;
+                best_distance = distance;
+                on_match(length, distance);
+            }
+        }
+        (best_length, best_distance)
+    }
+}
@@ -6,7 +6,7 @@ fn test_roundtrip1() {
         0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 1, 1, 2, 3, 4, 1, 1, 2, 3, 4, 5, 6, 7, 8, 9, 1, 3, 5, 7, 9,
     ];
     let encoded = encode(&xs);
-    let decoded = decode(&encoded);
+    let decoded = decode(&encoded).unwrap();
     eprintln!("encoded: {:?}", encoded);
     assert_eq!(&xs[..], &decoded);
 }
@@ -2671,7 +2671,1287 @@ fn test_roundtrip2() {
         </body>
         </html>"##.as_bytes();
     let encoded = encode(xs);
-    let decoded = decode(&encoded);
+    let decoded = decode(&encoded).unwrap();
     eprintln!("encoded: {:?}", encoded);
     assert_eq!(xs, &decoded);
 }
+
+/// n bytes of a slowly varying sequence, full of short and mid-length repeats
+fn sample(n: u64) -> Vec<u8> {
+    (0..n)
+        .map(|x| (x * x / 7 % 31) as u8 ^ (x / 1000) as u8)
+        .collect()
+}
+
+/// inputs aimed at the match finders of a window of 2^window_bits bytes: long runs,
+/// and random blocks repeated at exactly the window, and at one byte past it
+fn match_finder_inputs(window_bits: u32) -> [Vec<u8>; 3] {
+    let window = 1 << window_bits;
+    let mut state = 1u64;
+    let mut random = |n| -> Vec<u8> {
+        (0..n)
+            .map(|_| {
+                state = state
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                (state >> 56) as u8
+            })
+            .collect()
+    };
+    let edge = random(window);
+    let past = random(window + 1);
+    [
+        [vec![b'a'; 5000], vec![b'b'; 3], vec![b'a'; 70000]].concat(),
+        [&edge[..], &edge[..]].concat(),
+        [&past[..], &past[..]].concat(),
+    ]
+}
+
+/// whether the tokens of the match_finder_inputs decode, and take the matches there are
+fn check_match_finder_inputs(window_bits: u32, tokens: [Vec<u16>; 3]) {
+    let vocab = Vocab::new(window_bits).unwrap();
+    let inputs = match_finder_inputs(window_bits);
+    for (xs, tokens) in inputs.iter().zip(&tokens) {
+        assert_eq!(decode_with_vocab(tokens, vocab, &[]).unwrap(), *xs);
+    }
+    let [runs, edge, past] = tokens;
+    assert!(runs.len() < 1000);
+    // the second copy is one match after another
+    assert!(edge.len() < inputs[1].len() / 2 + inputs[1].len() / 32);
+    // out of reach, the second copy is mostly literals
+    assert!(past.len() > inputs[2].len() * 9 / 10);
+}
+
+#[test]
+fn test_roundtrip_levels() {
+    let xs = sample(100_000);
+    let mut prev = usize::MAX;
+    for level in 0..=9 {
+        let config = EncoderConfig::level(level).unwrap();
+        let encoded = encode_with_config(&xs, config);
+        let decoded = decode(&encoded).unwrap();
+        assert_eq!(xs, decoded, "level {}", level);
+        if level == 0 {
+            assert_eq!(encoded.len(), xs.len());
+        }
+        if level == 9 {
+            assert!(encoded.len() <= prev);
+        }
+        prev = encoded.len();
+    }
+    assert!(EncoderConfig::level(10).is_none());
+}
+
+#[test]
+fn test_roundtrip_optimal() {
+    let xs = sample(100_000);
+    let lazy = encode_with_config(&xs, EncoderConfig::default());
+    let config = EncoderConfig {
+        parsing: Parsing::Optimal,
+        ..Default::default()
+    };
+    let optimal = encode_with_config(&xs, config);
+    assert_eq!(xs, decode(&optimal).unwrap());
+    assert!(optimal.len() <= lazy.len());
+}
+
+#[test]
+fn test_cost_model() {
+    let xs = sample(20_000);
+    let config = EncoderConfig {
+        parsing: Parsing::Optimal,
+        ..Default::default()
+    };
+
+    // prohibitively expensive length tokens leave nothing but literals
+    let mut weights = vec![1.0; token::VOCAB_SIZE];
+    weights[256..512].fill(1000.0);
+    let encoded = encode_with_cost_model(&xs, config, TokenWeights::new(weights).unwrap());
+    assert_eq!(encoded, xs.iter().map(|&x| x as u16).collect::<Vec<_>>());
+
+    // no least-cost parse without finite, non-negative weights
+    for x in [f32::NAN, f32::INFINITY, f32::NEG_INFINITY, -1.0] {
+        let mut weights = vec![1.0; token::VOCAB_SIZE];
+        weights[b'x' as usize] = x;
+        assert_eq!(TokenWeights::new(weights), None);
+    }
+    assert_eq!(TokenWeights::new(vec![1.0; token::VOCAB_SIZE - 1]), None);
+
+    let mut counts = vec![0; token::VOCAB_SIZE];
+    for x in encode_with_config(&xs, config) {
+        counts[x as usize] += 1;
+    }
+    let encoded = encode_with_cost_model(&xs, config, TokenWeights::from_counts(&counts));
+    assert_eq!(xs, decode(&encoded).unwrap());
+}
+
+#[test]
+fn test_decode_error() {
+    let length = u16::from(Token::Length(0));
+    let d0 = u16::from(Token::Distance0(0));
+    let d1 = u16::from(Token::Distance1(0));
+    assert_eq!(
+        decode(&[1, 2, d0]),
+        Err(DecodeError::UnexpectedToken {
+            position: 2,
+            token: Token::Distance0(0)
+        })
+    );
+    assert_eq!(
+        decode(&[1, length, d0]),
+        Err(DecodeError::UnexpectedEnd { position: 3 })
+    );
+    assert_eq!(
+        decode(&[1, length, d1, d0]),
+        Err(DecodeError::UnexpectedToken {
+            position: 2,
+            token: Token::Distance1(0)
+        })
+    );
+    assert_eq!(
+        decode(&[1, length, u16::from(Token::Distance0(1)), d1]),
+        Err(DecodeError::InvalidDistance {
+            position: 1,
+            distance: 2
+        })
+    );
+    assert_eq!(
+        decode(&[1, 1000]),
+        Err(DecodeError::InvalidToken {
+            position: 1,
+            id: 1000
+        })
+    );
+    assert_eq!(decode(&[1, length, d0, d1]), Ok(vec![1; 4]));
+
+    // the decoder checks the codes too
+    let codes = [
+        lz77::Lz77::Literal(1),
+        lz77::Lz77::Dictionary {
+            length: 0,
+            distance: 2,
+        },
+    ];
+    assert_eq!(
+        Decoder::new(codes.into_iter().map(Ok)).collect::<Result<Vec<u8>, _>>(),
+        Err(DecodeError::InvalidDistance {
+            position: 1,
+            distance: 2
+        })
+    );
+}
+
+#[test]
+fn test_decode_lenient() {
+    let length = u16::from(Token::Length(0));
+    let d0 = u16::from(Token::Distance0(0));
+    let d1 = u16::from(Token::Distance1(0));
+    let far = u16::from(Token::Distance0(9));
+
+    let (decoded, repairs) = decode_lenient(
+        &[d1, 1, 1000, length, d0, 2, length, far, d1, length],
+        Vocab::default(),
+    );
+    assert_eq!(decoded, vec![1, 1, 1, 1, 2, 1, 1, 1]);
+    assert_eq!(
+        repairs,
+        vec![
+            Repair::Dropped {
+                position: 0,
+                token: Token::Distance1(0)
+            },
+            Repair::InvalidToken {
+                position: 2,
+                id: 1000
+            },
+            Repair::MissingDistance1 { position: 3 },
+            Repair::ClampedDistance {
+                position: 6,
+                distance: 10
+            },
+            Repair::Dropped {
+                position: 9,
+                token: Token::Length(0)
+            },
+        ]
+    );
+
+    let xs = b"abcabcabcabcabc";
+    let encoded = encode(xs);
+    assert_eq!(
+        decode_lenient(&encoded, Vocab::default()),
+        (xs.to_vec(), vec![])
+    );
+
+    // ids of other windows
+    let xs = [&include_str!("test.rs").as_bytes()[..20000], &[b'a'; 3000]].concat();
+    for window_bits in [8, 20] {
+        let config = EncoderConfig {
+            window_bits,
+            ..Default::default()
+        };
+        let encoded = encode_with_config(&xs, config);
+        assert_eq!(
+            decode_lenient(&encoded, config.vocab()),
+            (xs.clone(), vec![])
+        );
+    }
+}
+
+#[test]
+fn test_token_grammar() {
+    let xs = b"abcabcabcabcabc".repeat(100);
+    let mut grammar = TokenGrammar::new();
+    assert!(!grammar.is_valid(Token::Length(0)));
+    for x in encode(&xs) {
+        let mask = grammar.mask();
+        assert_eq!(mask[x as usize / 64] >> (x % 64) & 1, 1);
+        grammar.push(Token::from(x)).unwrap();
+    }
+    assert!(grammar.is_complete());
+    assert_eq!(grammar.len(), xs.len());
+
+    let mut grammar = TokenGrammar::new();
+    grammar.push(Token::Literal(1)).unwrap();
+    grammar.push(Token::Length(0)).unwrap();
+    let mask = grammar.mask();
+    let d0 = u16::from(Token::Distance0(0)) as usize;
+    assert_eq!(mask.iter().map(|x| x.count_ones()).sum::<u32>(), 1);
+    assert_eq!(mask[d0 / 64] >> (d0 % 64) & 1, 1);
+    assert!(grammar.push(Token::Distance0(1)).is_err());
+    assert!(grammar.push(Token::Literal(1)).is_err());
+}
+
+#[test]
+fn test_meta_tokens() {
+    let xs = b"abcabcabcabcabc";
+    let encoded = encode_tokens(
+        xs,
+        EncoderConfig::default(),
+        TokenCount,
+        &[],
+        true,
+        16,
+        usize::MAX,
+    );
+    let sos = u16::from(Token::Meta(MetaToken::StartOfSentence));
+    let eos = u16::from(Token::Meta(MetaToken::EndOfSentence));
+    let pad = u16::from(Token::Meta(MetaToken::Pad));
+    assert_eq!(encoded.len(), 16);
+    assert_eq!(encoded[0], sos);
+    assert_eq!(
+        &encoded[encoded.len() - 8..],
+        &[eos, pad, pad, pad, pad, pad, pad, pad]
+    );
+    assert_eq!(decode(&encoded).unwrap(), xs);
+
+    let mut grammar = TokenGrammar::new();
+    for &x in &encoded {
+        grammar.push(Token::from(x)).unwrap();
+    }
+    assert!(!grammar.is_valid(Token::Literal(0)));
+
+    // everything after EndOfSentence is ignored
+    let mut encoded = encode(xs);
+    encoded.extend([eos, u16::from(Token::Distance0(0))]);
+    assert_eq!(decode(&encoded).unwrap(), xs);
+    assert!(decode(&[1, sos]).is_err());
+}
+
+#[test]
+fn test_encode_batch() {
+    let xs: [&[u8]; 3] = [b"abcabcabcabcabc", b"", b"abcdefghijklmnopqrstuvwxyz"];
+    let pad = u16::from(Token::Meta(MetaToken::Pad));
+    let (tokens, mask) = encode_batch(&xs, EncoderConfig::default(), false, Some(5), true, true);
+    assert_eq!(tokens.shape(), &[3, 5]);
+    // abca + Length/Distance0/Distance1 does not fit in 5 tokens
+    assert_eq!(tokens.row(0).to_vec(), vec![97, 98, 99, 97, pad]);
+    assert_eq!(mask.row(0).to_vec(), vec![true, true, true, true, false]);
+    assert_eq!(mask.row(1).to_vec(), vec![false; 5]);
+    assert_eq!(decode(&tokens.row(2).to_vec()).unwrap(), b"abcde");
+
+    let (tokens, mask) = encode_batch(&xs, EncoderConfig::default(), true, None, false, true);
+    assert_eq!(tokens.shape(), &[3, 28]);
+    assert_eq!(mask.row(1).iter().filter(|&&x| x).count(), 2);
+    for (row, x) in tokens.rows().into_iter().zip(xs) {
+        assert_eq!(decode(&row.to_vec()).unwrap(), x);
+    }
+
+    // room for nothing but StartOfSentence and EndOfSentence
+    let (tokens, mask) = encode_batch(&xs, EncoderConfig::default(), true, Some(2), true, true);
+    assert_eq!(tokens.shape(), &[3, 2]);
+    assert!(mask.iter().all(|&x| x));
+    for row in tokens.rows() {
+        assert_eq!(decode(&row.to_vec()).unwrap(), b"");
+    }
+}
+
+#[test]
+#[should_panic(expected = "max_len must fit StartOfSentence and EndOfSentence")]
+fn test_encode_batch_short() {
+    let xs: [&[u8]; 1] = [b"hello world"];
+    encode_batch(&xs, EncoderConfig::default(), true, Some(1), true, true);
+}
+
+#[test]
+fn test_decode_batch() {
+    let xs: [&[u8]; 3] = [b"abcabcabcabcabc", b"", b"abcdefghijklmnopqrstuvwxyz"];
+    let (tokens, _) = encode_batch(&xs, EncoderConfig::default(), false, None, true, false);
+    let rows: Vec<Vec<u16>> = tokens.rows().into_iter().map(|row| row.to_vec()).collect();
+    let mut rows: Vec<&[u16]> = rows.iter().map(Vec::as_slice).collect();
+    let results = decode_batch(&rows, Vocab::default());
+    for (result, x) in results.into_iter().zip(xs) {
+        assert_eq!(result.unwrap(), x);
+    }
+
+    rows[1] = &[1000];
+    assert!(decode_batch(&rows, Vocab::default())[1].is_err());
+
+    // a non-default window round trips with its vocabulary
+    let config = EncoderConfig {
+        window_bits: 10,
+        ..Default::default()
+    };
+    let xs = [&[b'a'; 2000][..], b"abcabcabcabcabc"];
+    let (tokens, _) = encode_batch(&xs, config, false, None, true, false);
+    let rows: Vec<Vec<u16>> = tokens.rows().into_iter().map(|row| row.to_vec()).collect();
+    let rows: Vec<&[u16]> = rows.iter().map(Vec::as_slice).collect();
+    for (result, x) in decode_batch(&rows, config.vocab()).into_iter().zip(xs) {
+        assert_eq!(result.unwrap(), x);
+    }
+}
+
+#[test]
+fn test_stream() {
+    let xs = sample(100_000);
+    for parsing in [Parsing::Lazy, Parsing::Optimal] {
+        let config = EncoderConfig {
+            parsing,
+            ..Default::default()
+        };
+        let expected = encode_with_config(&xs, config);
+        // chunks that wrap the queue around at odd offsets, too
+        let mut tokens = Vec::new();
+        for chunk_size in [257, 3333, 10_000, 16385, 40000] {
+            let mut encoder = StreamEncoder::new(config, false);
+            tokens.clear();
+            for chunk in xs.chunks(chunk_size) {
+                tokens.extend(encoder.feed(chunk));
+            }
+            tokens.extend(encoder.finish());
+            let ids: Vec<u16> = tokens.iter().map(|&x| u16::from(x)).collect();
+            assert_eq!(ids, expected, "chunk size {}", chunk_size);
+        }
+
+        let mut decoder = StreamDecoder::new();
+        let mut decoded = Vec::new();
+        for &token in &tokens {
+            decoded.extend(decoder.feed(&[token]).unwrap());
+        }
+        decoder.finish().unwrap();
+        assert_eq!(decoded, xs);
+    }
+
+    let mut decoder = StreamDecoder::new();
+    assert_eq!(
+        decoder.feed(&[Token::Literal(1), Token::Length(0)]),
+        Ok(vec![1])
+    );
+    assert_eq!(decoder.feed(&[Token::Distance0(0)]), Ok(vec![]));
+    assert!(decoder.finish().is_err());
+
+    // a bad token takes none of the tokens before it
+    let mut decoder = StreamDecoder::new();
+    let tokens = [Token::Literal(1), Token::Literal(2), Token::Distance0(0)];
+    assert!(decoder.feed(&tokens).is_err());
+    assert_eq!(decoder.position(), 0);
+    assert_eq!(decoder.feed(&tokens[..2]), Ok(vec![1, 2]));
+    decoder.finish().unwrap();
+}
+
+#[test]
+fn test_encode_with_offsets() {
+    let xs = b"abcdabcdabcdx";
+    let (tokens, offsets) = encode_with_offsets(xs, EncoderConfig::default());
+    assert_eq!(tokens, encode(xs));
+    assert_eq!(
+        offsets,
+        vec![
+            (0, 1),
+            (1, 2),
+            (2, 3),
+            (3, 4),
+            (4, 5),
+            (5, 12),
+            (5, 12),
+            (5, 12),
+            (12, 13)
+        ]
+    );
+    for (&x, &(start, end)) in tokens.iter().zip(&offsets) {
+        if let Token::Literal(x) = Token::from(x) {
+            assert_eq!(&xs[start..end], &[x]);
+        }
+    }
+}
+
+#[test]
+fn test_compress() {
+    let empty: &[u8] = &[];
+    let raw = compress(empty, Vec::new(), EncoderConfig::default(), Format::Raw).unwrap();
+    assert_eq!(raw, vec![0x03, 0x00]);
+    let zlib = compress(empty, Vec::new(), EncoderConfig::default(), Format::Zlib).unwrap();
+    assert_eq!(zlib, vec![0x78, 0x9C, 0x03, 0x00, 0x00, 0x00, 0x00, 0x01]);
+
+    let xs = b"abcabcabcabcabc";
+    let gzip = compress(&xs[..], Vec::new(), EncoderConfig::default(), Format::Gzip).unwrap();
+    let mut crc = checksum::Crc32::new();
+    crc.update(xs);
+    let n = gzip.len();
+    assert_eq!(&gzip[..3], &[0x1F, 0x8B, 8]);
+    assert_eq!(&gzip[n - 8..n - 4], &crc.get().to_le_bytes());
+    assert_eq!(&gzip[n - 4..], &(xs.len() as u32).to_le_bytes());
+
+    // fixed Huffman blocks, byte for byte what zlib -6 writes
+    let raw = compress(&xs[..], Vec::new(), EncoderConfig::default(), Format::Raw).unwrap();
+    assert_eq!(raw, vec![0x4B, 0x4C, 0x4A, 0x4E, 0x44, 0x42, 0x00]);
+    let xs = b"hello hello hello, world\n";
+    let raw = compress(&xs[..], Vec::new(), EncoderConfig::default(), Format::Raw).unwrap();
+    assert_eq!(
+        raw,
+        vec![
+            0xCB, 0x48, 0xCD, 0xC9, 0xC9, 0x57, 0xC8, 0x40, 0x90, 0x3A, 0x0A, 0xE5, 0xF9, 0x45,
+            0x39, 0x29, 0x5C, 0x00
+        ]
+    );
+}
+
+#[test]
+fn test_encode_deflate() {
+    let xs = sample(50000);
+    for format in [Format::Raw, Format::Zlib, Format::Gzip] {
+        for config in [EncoderConfig::default(), EncoderConfig::level(1).unwrap()] {
+            let compressed = compress(xs.as_slice(), Vec::new(), config, format).unwrap();
+            let tokens = encode_deflate(&compressed, format).unwrap();
+            assert_eq!(tokens, encode_with_config(&xs, config));
+            assert_eq!(decode(&tokens).unwrap(), xs);
+        }
+    }
+
+    // stored block
+    let stored = [0x01, 0x03, 0x00, 0xFC, 0xFF, b'a', b'b', b'c'];
+    let tokens = encode_deflate(&stored, Format::Raw).unwrap();
+    assert_eq!(decode(&tokens).unwrap(), b"abc");
+
+    // concatenated gzip members
+    let mut gzip = compress(
+        &b"abcabcabcabc"[..],
+        Vec::new(),
+        EncoderConfig::default(),
+        Format::Gzip,
+    )
+    .unwrap();
+    gzip.extend(
+        compress(
+            &b"xyz"[..],
+            Vec::new(),
+            EncoderConfig::default(),
+            Format::Gzip,
+        )
+        .unwrap(),
+    );
+    let tokens = encode_deflate(&gzip, Format::Gzip).unwrap();
+    assert_eq!(decode(&tokens).unwrap(), b"abcabcabcabcxyz");
+
+    // zero padding after the last member, which gzip -d ignores, but nothing else
+    let padded = [&gzip[..], &[0; 100]].concat();
+    assert_eq!(encode_deflate(&padded, Format::Gzip).unwrap(), tokens);
+    assert!(encode_deflate(&[&padded[..], &[1]].concat(), Format::Gzip).is_err());
+
+    // a member cannot reach back into the one before it: length 3 at distance 1 first
+    let mut member = vec![0x1F, 0x8B, 8, 0, 0, 0, 0, 0, 0, 0xFF, 0x03, 0x02, 0x00];
+    member.extend([0; 8]);
+    assert!(matches!(
+        encode_deflate(&[&gzip[..], &member].concat(), Format::Gzip),
+        Err(inflate::InflateError::InvalidDistance { distance: 1 })
+    ));
+
+    let n = gzip.len();
+    assert!(matches!(
+        encode_deflate(&gzip[..n - 1], Format::Gzip),
+        Err(inflate::InflateError::UnexpectedEnd)
+    ));
+    gzip[n - 8] ^= 1;
+    assert!(matches!(
+        encode_deflate(&gzip, Format::Gzip),
+        Err(inflate::InflateError::ChecksumMismatch)
+    ));
+    assert!(matches!(
+        encode_deflate(&[0x01, 0x03, 0x00, 0xFC, 0xFE], Format::Raw),
+        Err(inflate::InflateError::InvalidStoredLength)
+    ));
+    assert!(matches!(
+        encode_deflate(&[0x07], Format::Raw),
+        Err(inflate::InflateError::InvalidBlockType)
+    ));
+}
+
+#[test]
+fn test_token_file() {
+    use crate::token_file::{TokenFile, TokenFormat};
+
+    let documents = vec![
+        encode(b"abcabcabcabc"),
+        encode(b"xyz"),
+        encode(b"hello hello"),
+    ];
+    for file in [
+        TokenFile::new(encode(b"abcabcabcabc")),
+        TokenFile::new(Vec::new()),
+        TokenFile::from_documents(&documents),
+        TokenFile::from_documents(&documents[..1]),
+        TokenFile::from_documents(&[Vec::new()]),
+        TokenFile::from_documents(&[Vec::new(), documents[1].clone(), Vec::new()]),
+    ] {
+        for format in [TokenFormat::Text, TokenFormat::Bin, TokenFormat::Json] {
+            let mut bytes = Vec::new();
+            file.write(&mut bytes, format).unwrap();
+            assert_eq!(
+                TokenFile::read(bytes.as_slice(), format, Vocab::default()).unwrap(),
+                file
+            );
+            // the u64 ends are aligned, whether the number of tokens is odd or even
+            if format == TokenFormat::Bin {
+                assert_eq!(bytes.len() % 8, 0);
+            }
+        }
+    }
+    let file = TokenFile::from_documents(&documents);
+    assert_eq!(
+        file.documents(),
+        documents.iter().map(Vec::as_slice).collect::<Vec<_>>()
+    );
+
+    let mut bytes = Vec::new();
+    file.write(&mut bytes, TokenFormat::Bin).unwrap();
+    assert_eq!(&bytes[..4], b"LZTK");
+    assert_eq!(
+        bytes.len(),
+        (28 + 2 * file.tokens.len()).next_multiple_of(8) + 8 * documents.len()
+    );
+    assert!(TokenFile::read(&bytes[1..], TokenFormat::Bin, Vocab::default()).is_err());
+    assert!(TokenFile::read(
+        &bytes[..bytes.len() - 1],
+        TokenFormat::Bin,
+        Vocab::default()
+    )
+    .is_err());
+    bytes[28] = 0xFF;
+    bytes[29] = 0xFF;
+    assert!(TokenFile::read(bytes.as_slice(), TokenFormat::Bin, Vocab::default()).is_err());
+    // the number of tokens or documents overflows the length check
+    for (i, x) in [(19, 0x80), (27, 0x80), (19, 0x40)] {
+        let mut bytes = bytes.clone();
+        bytes[i] = x;
+        assert!(TokenFile::read(bytes.as_slice(), TokenFormat::Bin, Vocab::default()).is_err());
+    }
+    assert!(TokenFile::read(&b"1\n2\nx\n"[..], TokenFormat::Text, Vocab::default()).is_err());
+    // documents that are only separated by an empty line, without the last terminator
+    let file = TokenFile::read(&b"1\n2\n\n3\n"[..], TokenFormat::Text, Vocab::default());
+    assert_eq!(file.unwrap().documents, Some(vec![2, 3]));
+    assert!(TokenFile::read(
+        &b"{\"version\":1,\"vocab_size\":3,\"tokens\":[]}"[..],
+        TokenFormat::Json,
+        Vocab::default()
+    )
+    .is_err());
+}
+
+#[test]
+fn test_pack() {
+    use crate::pack::{pack, read_shard, ShardWriter};
+
+    let dir = std::env::temp_dir().join(format!("lz_test_pack_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let documents: Vec<Vec<u8>> = (0..20u64)
+        .map(|i| {
+            (0..i * 100)
+                .map(|x| (x * x / 7 % 31) as u8 ^ i as u8)
+                .collect()
+        })
+        .collect();
+    let paths: Vec<_> = documents
+        .iter()
+        .enumerate()
+        .map(|(i, document)| {
+            let path = dir.join(format!("{:02}.txt", i));
+            std::fs::write(&path, document).unwrap();
+            path
+        })
+        .collect();
+
+    let shards = pack(&paths, dir.join("ds"), 500, EncoderConfig::default()).unwrap();
+    let mut tokens = Vec::new();
+    let mut starts = Vec::new();
+    for (i, shard) in shards.iter().enumerate() {
+        let (shard_tokens, shard_starts) = read_shard(shard).unwrap();
+        if i + 1 < shards.len() {
+            assert_eq!(shard_tokens.len(), 500);
+        }
+        starts.extend(
+            shard_starts
+                .iter()
+                .map(|&start| start as usize + tokens.len()),
+        );
+        tokens.extend(shard_tokens);
+    }
+    assert_eq!(starts.len(), documents.len());
+    let eos = u16::from(Token::Meta(MetaToken::EndOfSentence));
+    for (i, document) in documents.iter().enumerate() {
+        let end = starts.get(i + 1).map_or(tokens.len(), |&start| start - 1);
+        if i + 1 < documents.len() {
+            assert_eq!(tokens[end], eos);
+        }
+        assert_eq!(decode(&tokens[starts[i]..end]).unwrap(), *document);
+    }
+
+    // the EndOfSentence fills a shard, so the next document starts the next one
+    let mut writer = ShardWriter::new(dir.join("boundary"), 4);
+    writer.push(&[1, 2, 3]).unwrap();
+    writer.push(&[5, 6]).unwrap();
+    writer.push(&[7]).unwrap();
+    writer.push(&[]).unwrap();
+    let shards: Vec<_> = writer
+        .finish()
+        .unwrap()
+        .iter()
+        .map(|shard| read_shard(shard).unwrap())
+        .collect();
+    assert_eq!(
+        shards,
+        [
+            (vec![1, 2, 3, eos], vec![0]),
+            (vec![5, 6, eos, 7], vec![0, 3]),
+            (vec![eos], vec![1]),
+        ]
+    );
+    for (tokens, starts) in &shards {
+        assert!(starts.iter().all(|&start| start as usize <= tokens.len()));
+    }
+
+    let writer = ShardWriter::new(dir.join("empty"), 10);
+    let shards = writer.finish().unwrap();
+    assert_eq!(read_shard(&shards[0]).unwrap(), (vec![], vec![]));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn test_list_files() {
+    use crate::pack::list_files;
+    use std::os::unix::fs::symlink;
+    use std::os::unix::net::UnixListener;
+
+    let dir = std::env::temp_dir().join(format!("lz_test_list_files_{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("b")).unwrap();
+    std::fs::write(dir.join("a"), b"a").unwrap();
+    std::fs::write(dir.join("b/c"), b"c").unwrap();
+    // a cycle, a link to a file, a dangling link and a socket
+    symlink(&dir, dir.join("b/loop")).unwrap();
+    symlink(dir.join("a"), dir.join("d")).unwrap();
+    symlink(dir.join("missing"), dir.join("e")).unwrap();
+    let _socket = UnixListener::bind(dir.join("f")).unwrap();
+
+    let files = list_files(&dir).unwrap();
+    assert_eq!(files, vec![dir.join("a"), dir.join("b/c"), dir.join("d")]);
+    assert_eq!(list_files(dir.join("a")).unwrap(), vec![dir.join("a")]);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_dictionary() {
+    let dictionary = b"<!DOCTYPE html><html><head><meta charset=\"utf-8\"></head><body>";
+    let xs = b"<!DOCTYPE html><html><head><title>hello</title></head><body>hello</body></html>";
+    let tokens = encode_with_dictionary(xs, EncoderConfig::default(), dictionary);
+    assert!(matches!(Token::from(tokens[0]), Token::Length(_)));
+    assert!(tokens.len() < encode(xs).len());
+    assert_eq!(decode_with_dictionary(&tokens, dictionary).unwrap(), xs);
+    assert!(matches!(
+        decode(&tokens),
+        Err(DecodeError::InvalidDistance { position: 0, .. })
+    ));
+
+    let xs = sample(100000);
+    let (dictionary, xs) = xs.split_at(40000);
+    for config in [
+        EncoderConfig::default(),
+        EncoderConfig::level(1).unwrap(),
+        EncoderConfig {
+            parsing: Parsing::Optimal,
+            ..Default::default()
+        },
+    ] {
+        for xs in [&xs[..0], &xs[..3], &xs[..100], xs] {
+            let tokens = encode_with_dictionary(xs, config, dictionary);
+            assert_eq!(decode_with_dictionary(&tokens, dictionary).unwrap(), xs);
+        }
+    }
+    let tokens = encode_with_dictionary(b"abcd", EncoderConfig::default(), b"abc");
+    assert_eq!(decode_with_dictionary(&tokens, b"abc").unwrap(), b"abcd");
+
+    // dictionaries shorter than min_match are used as well, on both sides
+    for dictionary in [&b"a"[..], b"ab", b"abc"] {
+        for hash_len in [3, 5] {
+            let config = EncoderConfig {
+                hash_len,
+                ..Default::default()
+            };
+            let xs = b"abababababababab";
+            let tokens = encode_with_dictionary(xs, config, dictionary);
+            assert_eq!(decode_with_dictionary(&tokens, dictionary).unwrap(), xs);
+        }
+    }
+    let tokens = encode_with_dictionary(b"ababababab", EncoderConfig::default(), b"ab");
+    assert!(matches!(Token::from(tokens[0]), Token::Length(_)));
+
+    // an adapter told of a dictionary the decoder lacks
+    let tokens = encode_with_dictionary(xs, EncoderConfig::default(), dictionary);
+    let adaptor = DecoderAdapter::new(tokens.iter().map(|&x| Token::from(x)))
+        .with_dictionary_len(dictionary.len());
+    assert!(matches!(
+        Decoder::new(adaptor).collect::<Result<Vec<u8>, _>>(),
+        Err(DecodeError::InvalidDistance { .. })
+    ));
+}
+
+#[test]
+fn test_train_dictionary() {
+    use crate::dictionary::{token_count, train_dictionary};
+
+    let samples: Vec<Vec<u8>> = (0..50u64)
+        .map(|i| {
+            let body: String = (0..i % 7 + 3)
+                .map(|j| format!("<li id=\"item-{}\">{}</li>", j, i * j * 7919 % 1000))
+                .collect();
+            format!(
+                "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>page {}</title></head><body><ul>{}</ul></body></html>",
+                i, body
+            )
+            .into_bytes()
+        })
+        .collect();
+    let samples: Vec<&[u8]> = samples.iter().map(Vec::as_slice).collect();
+    let dictionary = train_dictionary(&samples, 256);
+    assert!(!dictionary.is_empty() && dictionary.len() <= 256);
+    let config = EncoderConfig::default();
+    assert!(token_count(&samples, &dictionary, config) < token_count(&samples, &[], config));
+    for sample in &samples {
+        let tokens = encode_with_dictionary(sample, config, &dictionary);
+        assert_eq!(
+            decode_with_dictionary(&tokens, &dictionary).unwrap(),
+            *sample
+        );
+    }
+
+    // the whole corpus fits
+    assert_eq!(train_dictionary(&samples[..1], 1 << 20), samples[0]);
+    assert!(train_dictionary(&[], 256).is_empty());
+    assert!(train_dictionary(&samples, 0).is_empty());
+    // shorter than any segment
+    assert!(train_dictionary(&[b"0123456789"], 4).len() <= 4);
+}
+
+#[test]
+fn test_window_bits() {
+    assert_eq!(Vocab::default().size(), 899);
+    assert_eq!(Vocab::new(12).unwrap().size(), 787);
+    assert_eq!(Vocab::new(20).unwrap().size(), 4867);
+    assert!(Vocab::new(7).is_none() && Vocab::new(21).is_none());
+    for bits in [8, 12, 15, 16, 20] {
+        let vocab = Vocab::new(bits).unwrap();
+        assert_eq!(Vocab::from_size(vocab.size()), Some(vocab));
+        for id in 0..vocab.size() as u16 {
+            assert_eq!(vocab.id(vocab.token(id).unwrap()), id);
+        }
+        assert_eq!(vocab.token(vocab.size() as u16), None);
+    }
+
+    // a pseudo-random block repeated 64k apart is only matched by windows that reach it
+    let mut seed = 1u32;
+    let block: Vec<u8> = (0..1 << 16)
+        .map(|_| {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            (seed >> 16) as u8
+        })
+        .collect();
+    let xs = [block.as_slice(), &block, &block[..1000]].concat();
+    let mut counts = Vec::new();
+    for bits in [12, 15, 16, 20] {
+        let config = EncoderConfig {
+            window_bits: bits,
+            ..Default::default()
+        };
+        let vocab = config.vocab();
+        let tokens = encode_with_config(&xs, config);
+        assert!(tokens.iter().all(|&x| (x as usize) < vocab.size()));
+        assert_eq!(decode_with_vocab(&tokens, vocab, &[]).unwrap(), xs);
+        counts.push(tokens.len());
+
+        let encoder = Encoder::with_config(xs.as_slice(), config, false);
+        for x in encoder {
+            if let lz77::Lz77::Dictionary { distance, .. } = x {
+                assert!(distance as usize <= 1 << bits);
+            }
+        }
+    }
+    assert_eq!(counts[0], counts[1]);
+    assert!(counts[2] < block.len() + 1000 && counts[3] < block.len() + 1000);
+
+    // matches much longer than a small window, e.g., of runs, pending as the decoder slides
+    let xs = [&[b'a'; 100_000][..], &block[..3000], &[b'b'; 5000]].concat();
+    let config = EncoderConfig {
+        window_bits: 8,
+        ..Default::default()
+    };
+    let tokens = encode_with_config(&xs, config);
+    assert_eq!(decode_with_vocab(&tokens, config.vocab(), &[]).unwrap(), xs);
+}
+
+#[test]
+fn test_vocabulary() {
+    use crate::vocabulary::{DeflateVocab, JointVocab, Layout, LogVocab, Vocabulary};
+
+    assert_eq!(DeflateVocab::default().vocab_size(), 574);
+    assert_eq!(JointVocab::new(8).unwrap().vocab_size(), 771);
+    assert_eq!(LogVocab::default().vocab_size(), 787);
+    assert!(JointVocab::new(16).is_none());
+    assert_eq!(
+        LogVocab::default().meta_id(MetaToken::Pad) as usize,
+        LogVocab::default().vocab_size() - 1
+    );
+
+    let xs = sample(100000);
+    let config = EncoderConfig::default();
+    // the default layout gives the same ids as encode
+    assert_eq!(
+        encode_with_vocabulary(&xs, config, &Vocab::default()),
+        encode(&xs)
+    );
+    for layout in ["bytes", "deflate", "joint", "log"] {
+        let layout = Layout::parse(layout).unwrap();
+        for bits in [8, 12, 15, 20] {
+            let Some(vocab) = layout.vocabulary(bits) else {
+                assert_eq!((layout, bits), (Layout::Joint, 20));
+                continue;
+            };
+            for xs in [&xs[..0], &xs[..10], &xs[..1000], &xs] {
+                let ids = encode_with_vocabulary(xs, config, vocab.as_ref());
+                assert!(ids.iter().all(|&x| (x as usize) < vocab.vocab_size()));
+                assert_eq!(decode_with_vocabulary(&ids, vocab.as_ref()).unwrap(), xs);
+            }
+        }
+    }
+
+    // every distance of the window round-trips
+    for bits in [8, 12] {
+        let layouts: [Box<dyn Vocabulary>; 3] = [
+            Box::new(DeflateVocab::new(bits).unwrap()),
+            Box::new(JointVocab::new(bits).unwrap()),
+            Box::new(LogVocab::new(bits).unwrap()),
+        ];
+        for vocab in layouts {
+            for distance in 1..=1u32 << bits {
+                for length in [0, 8, 255] {
+                    let code = lz77::Lz77::Dictionary { length, distance };
+                    let mut ids = Vec::new();
+                    vocab.push_ids(code, &mut ids);
+                    let (symbol, n) = vocab.parse(&ids).unwrap();
+                    assert_eq!(symbol, vocabulary::Symbol::Code(code));
+                    assert_eq!(n, ids.len());
+                    for i in 0..ids.len() {
+                        assert_eq!(vocab.parse(&ids[..i]), Err(vocabulary::ParseError::End));
+                    }
+                }
+            }
+        }
+    }
+
+    let vocab = DeflateVocab::default();
+    let ids = encode_with_vocabulary(b"abcabcabcabc", config, &vocab);
+    assert!(matches!(
+        decode_with_vocabulary(&ids[..ids.len() - 1], &vocab),
+        Err(DecodeError::UnexpectedEnd { .. })
+    ));
+    assert!(matches!(
+        decode_with_vocabulary(&[97, 574], &vocab),
+        Err(DecodeError::InvalidToken {
+            position: 1,
+            id: 574
+        })
+    ));
+    // an extra bits token without a code
+    assert!(matches!(
+        decode_with_vocabulary(&[97, 400], &vocab),
+        Err(DecodeError::UnexpectedId {
+            position: 1,
+            id: 400
+        })
+    ));
+    let (sos, eos) = (
+        vocab.meta_id(MetaToken::StartOfSentence),
+        vocab.meta_id(MetaToken::EndOfSentence),
+    );
+    let ids = [&[sos][..], &ids, &[eos, 97]].concat();
+    assert_eq!(
+        decode_with_vocabulary(&ids, &vocab).unwrap(),
+        b"abcabcabcabc"
+    );
+    // a match before the first byte
+    let mut ids = vec![97];
+    vocab.push_ids(
+        lz77::Lz77::Dictionary {
+            length: 0,
+            distance: 2,
+        },
+        &mut ids,
+    );
+    assert!(matches!(
+        decode_with_vocabulary(&ids, &vocab),
+        Err(DecodeError::InvalidDistance {
+            position: 1,
+            distance: 2
+        })
+    ));
+}
+
+#[test]
+fn test_binary_tree() {
+    use crate::binary_tree::BinaryTree;
+
+    let html = include_str!("test.rs").as_bytes();
+    let xs = sample(100_000);
+    for xs in [&xs[..0], &xs[..3], &xs[..100], &xs[..20000], &html[..50000]] {
+        for level in [0, 1, 4, 6, 9] {
+            let config = EncoderConfig::level(level).unwrap();
+            let encoded = encode_with_match_finder(xs, config, BinaryTree::new(&config));
+            assert_eq!(decode(&encoded).unwrap(), xs, "level {}", level);
+        }
+        for window_bits in [8, 16, 20] {
+            let config = EncoderConfig {
+                parsing: Parsing::Optimal,
+                window_bits,
+                ..Default::default()
+            };
+            let encoded = encode_with_match_finder(xs, config, BinaryTree::new(&config));
+            assert_eq!(
+                decode_with_vocab(&encoded, config.vocab(), &[]).unwrap(),
+                xs
+            );
+        }
+    }
+
+    // runs, and repeats at the edge of the window
+    for window_bits in [8, 15] {
+        for parsing in [Parsing::Lazy, Parsing::Optimal] {
+            let config = EncoderConfig {
+                window_bits,
+                parsing,
+                ..Default::default()
+            };
+            let tokens = match_finder_inputs(window_bits)
+                .map(|xs| encode_with_match_finder(&xs, config, BinaryTree::new(&config)));
+            check_match_finder_inputs(window_bits, tokens);
+        }
+    }
+
+    // the tree finds the longest matches where the chain gives up
+    for config in [
+        EncoderConfig::level(6).unwrap(),
+        EncoderConfig {
+            parsing: Parsing::Optimal,
+            max_chain: 16,
+            ..Default::default()
+        },
+    ] {
+        let hash_chain = encode_with_config(&html[..100000], config);
+        let binary_tree =
+            encode_with_match_finder(&html[..100000], config, BinaryTree::new(&config));
+        assert!(binary_tree.len() < hash_chain.len());
+    }
+
+    // matches into the preset dictionary
+    let (dictionary, xs) = xs.split_at(40000);
+    let config = EncoderConfig::default();
+    let encoder =
+        Encoder::with_match_finder(xs, config, TokenCount, BinaryTree::new(&config), false)
+            .with_dictionary(dictionary);
+    let tokens: Vec<u16> = EncoderAdaptor::new(encoder).map(u16::from).collect();
+    assert_eq!(decode_with_dictionary(&tokens, dictionary).unwrap(), xs);
+    assert!(tokens.len() < encode(xs).len());
+
+    // chosen by the config, as --match-finder bt does
+    let config = EncoderConfig {
+        match_finder: MatchFinderKind::parse("bt").unwrap(),
+        ..EncoderConfig::level(9).unwrap()
+    };
+    assert_eq!(
+        encode_with_config(xs, config),
+        encode_with_match_finder(xs, config, BinaryTree::new(&config))
+    );
+    assert_eq!(decode(&encode_parallel(xs, config, 1 << 15)).unwrap(), xs);
+    assert_eq!(
+        MatchFinderKind::parse("hc"),
+        Some(MatchFinderKind::HashChain)
+    );
+    assert_eq!(MatchFinderKind::parse("bt4"), None);
+}
+
+#[test]
+fn test_suffix_array() {
+    use crate::binary_tree::BinaryTree;
+    use crate::suffix_array::SuffixArray;
+
+    let html = &include_str!("test.rs").as_bytes()[..20000];
+    let xs = sample(100_000);
+    let runs = [vec![b'a'; 1000], vec![b'b'; 10], vec![b'a'; 300]].concat();
+    for xs in [&xs[..0], &xs[..3], &xs[..100], &xs[..10000], html, &runs] {
+        for window_bits in [8, 15] {
+            let config = EncoderConfig {
+                window_bits,
+                ..Default::default()
+            };
+            let encoded = encode_offline(xs, config);
+            assert_eq!(
+                decode_with_vocab(&encoded, config.vocab(), &[]).unwrap(),
+                xs
+            );
+            // lazy parsing works too
+            let encoded = encode_with_match_finder(xs, config, SuffixArray::new(xs, &config));
+            assert_eq!(
+                decode_with_vocab(&encoded, config.vocab(), &[]).unwrap(),
+                xs
+            );
+        }
+    }
+
+    // runs, and repeats at the edge of the window
+    for window_bits in [8, 15] {
+        let config = EncoderConfig {
+            window_bits,
+            ..Default::default()
+        };
+        let tokens = match_finder_inputs(window_bits).map(|xs| encode_offline(&xs, config));
+        check_match_finder_inputs(window_bits, tokens);
+    }
+
+    // the longest match is exact, so no other finder does better
+    let config = EncoderConfig {
+        parsing: Parsing::Optimal,
+        ..Default::default()
+    };
+    let offline = encode_offline(html, config);
+    assert!(offline.len() <= encode_with_config(html, config).len());
+    let binary_tree = encode_with_match_finder(html, config, BinaryTree::new(&config));
+    assert!(offline.len() <= binary_tree.len());
+
+    // every match at a position is the longest one within the window
+    let xs = &xs[..3000];
+    let config = EncoderConfig {
+        window_bits: 8,
+        ..Default::default()
+    };
+    let mut finder = SuffixArray::new(xs, &config);
+    let buf = [xs, &vec![0; 4096 - xs.len()]].concat();
+    for pos in 0..xs.len() {
+        let (length, distance) = finder.find(&buf, pos, xs.len(), 3, 1, |_, _| {});
+        let longest = (pos.saturating_sub(1 << 8)..pos)
+            .map(|x| {
+                let n = lz77::MAX_LENGTH.min(xs.len() - pos);
+                xs[x..]
+                    .iter()
+                    .zip(&xs[pos..pos + n])
+                    .take_while(|(a, b)| a == b)
+                    .count()
+            })
+            .max()
+            .unwrap_or(0);
+        if longest >= 4 {
+            assert_eq!(length, longest, "pos {}", pos);
+            assert_eq!(xs[pos - distance..][..length], xs[pos..][..length]);
+        } else {
+            assert_eq!(distance, 0);
+        }
+    }
+}
+
+#[test]
+#[should_panic(expected = "the encoder must read the input given to SuffixArray::new")]
+fn test_suffix_array_other_input() {
+    use crate::suffix_array::SuffixArray;
+
+    let config = EncoderConfig::default();
+    let suffix_array = SuffixArray::new(b"abcabcabcabc", &config);
+    encode_with_match_finder(b"xyzxyzxyzxyz", config, suffix_array);
+}
+
+#[test]
+fn test_hash_params() {
+    use crate::hash::HashFunction;
+    use crate::lz77::Lz77;
+
+    let html = include_str!("test.rs").as_bytes();
+    let xs = sample(100_000);
+    let (dictionary, rest) = xs.split_at(30000);
+    // the binary tree hashes by the same parameters
+    for match_finder in [MatchFinderKind::HashChain, MatchFinderKind::BinaryTree] {
+        for hash_function in [HashFunction::ShiftXor, HashFunction::Multiplicative] {
+            for (hash_len, hash_bits, min_match) in [(3, 15, 3), (4, 8, 4), (4, 18, 6), (5, 20, 5)]
+            {
+                let config = EncoderConfig {
+                    hash_function,
+                    hash_len,
+                    hash_bits,
+                    min_match,
+                    match_finder,
+                    ..EncoderConfig::level(6).unwrap()
+                };
+                for xs in [&xs[..3], &xs[..20000], &html[..50000]] {
+                    let encoded = encode_with_config(xs, config);
+                    assert_eq!(decode(&encoded).unwrap(), xs);
+                    let optimal = EncoderConfig {
+                        parsing: Parsing::Optimal,
+                        ..config
+                    };
+                    let encoded = encode_with_config(xs, optimal);
+                    assert_eq!(decode(&encoded).unwrap(), xs);
+                }
+                let encoded = encode_with_dictionary(&rest[..20000], config, dictionary);
+                assert_eq!(
+                    decode_with_dictionary(&encoded, dictionary).unwrap(),
+                    &rest[..20000]
+                );
+
+                // no match is shorter than min_match
+                let encoder = Encoder::with_config(&html[..50000], config, false);
+                for code in encoder {
+                    if let Lz77::Dictionary { length, .. } = code {
+                        assert!(length as usize + 3 >= min_match);
+                    }
+                }
+            }
+        }
+    }
+    // the default hash is the 3-byte shift-xor one, and a 4-byte hash does no worse on text
+    let config = EncoderConfig::level(6).unwrap();
+    let default = encode_with_config(&html[..100000], config);
+    let wider = EncoderConfig {
+        hash_function: HashFunction::Multiplicative,
+        hash_len: 4,
+        ..config
+    };
+    assert!(encode_with_config(&html[..100000], wider).len() <= default.len());
+}
+
+#[test]
+fn test_hash_chain() {
+    use crate::match_finder::{HashChainFinder, MatchFinder};
+
+    // a period longer than the 64k buffer, so matches reach back across its wraps
+    let period = sample(40000);
+    let xs = [&period[..], &period[..], &period[..], &period[..100]].concat();
+    for window_bits in [15, 16, 20] {
+        let config = EncoderConfig {
+            window_bits,
+            ..Default::default()
+        };
+        let encoded = encode_with_config(&xs, config);
+        assert_eq!(
+            decode_with_vocab(&encoded, config.vocab(), &[]).unwrap(),
+            xs
+        );
+        // the repeats are found iff they are within the window
+        let per_period = encode_with_config(&period, config).len();
+        assert_eq!(
+            encoded.len() < per_period + 2000,
+            config.max_distance() >= period.len()
+        );
+    }
+
+    // runs, and repeats at the edge of the window
+    for window_bits in [8, 15] {
+        let config = EncoderConfig {
+            window_bits,
+            ..Default::default()
+        };
+        let tokens = match_finder_inputs(window_bits).map(|xs| encode_with_config(&xs, config));
+        check_match_finder_inputs(window_bits, tokens);
+    }
+
+    // positions past 2^32 wrap around in the chains
+    let config = EncoderConfig::default();
+    let mut finder = HashChainFinder::new(&config);
+    let buf_mask = (1 << 16) - 1;
+    let begin = (1 << 32) - 5000;
+    let mut buf = vec![0; buf_mask + 1];
+    for (i, &x) in xs[..10000].iter().enumerate() {
+        buf[(begin + i) & buf_mask] = x;
+    }
+    for pos in begin..begin + 9000 {
+        let mut found = Vec::new();
+        finder.find(&buf, pos, begin + 10000, 3, 1024, |l, d| found.push((l, d)));
+        for (length, distance) in found {
+            assert!(distance <= config.max_distance() && pos - distance >= begin);
+            let i = pos - begin;
+            assert_eq!(xs[i..i + length], xs[i - distance..i - distance + length]);
+        }
+    }
+}
+
+#[test]
+fn test_encode_parallel() {
+    let html = include_str!("test.rs").as_bytes();
+    let xs = sample(100_000);
+    for xs in [&xs[..0], &xs[..3], &xs[..3000], &xs[..], &html[..100000]] {
+        for block_size in [256, 10000, 65536, 1 << 20] {
+            if xs.len() / block_size > 100 {
+                continue;
+            }
+            for config in [
+                EncoderConfig::default(),
+                EncoderConfig::level(1).unwrap(),
+                EncoderConfig {
+                    parsing: Parsing::Optimal,
+                    window_bits: 8,
+                    ..Default::default()
+                },
+                EncoderConfig {
+                    window_bits: 20,
+                    ..Default::default()
+                },
+            ] {
+                if block_size < config.max_distance() {
+                    continue;
+                }
+                let encoded = encode_parallel(xs, config, block_size);
+                assert_eq!(
+                    decode_with_vocab(&encoded, config.vocab(), &[]).unwrap(),
+                    xs
+                );
+                let serial = encode_with_config(xs, config);
+                if xs.len() <= block_size {
+                    assert_eq!(encoded, serial);
+                } else {
+                    // only the matches cut off at the block boundaries are lost
+                    let blocks = xs.len().div_ceil(block_size);
+                    assert!(encoded.len() <= serial.len() + 3 * blocks);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+#[should_panic(expected = "block_size must be at least the window")]
+fn test_encode_parallel_small_blocks() {
+    encode_parallel(&[b'a'; 100_000], EncoderConfig::default(), 10000);
+}
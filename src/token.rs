@@ -1,4 +1,8 @@
-use crate::lz77::Lz77;
+use crate::decoder::{DecodeError, Repair};
+use crate::lz77::{Lz77, DEFAULT_WINDOW_BITS, MAX_WINDOW_BITS, MIN_WINDOW_BITS};
+
+/// 256 literals + 256 lengths + 256 + 128 distances + 3 meta tokens, for the default window
+pub const VOCAB_SIZE: usize = 899;
 
 #[derive(Clone, Copy, Eq, PartialEq, Debug)]
 pub enum MetaToken {
@@ -32,43 +36,128 @@ impl From<u16> for MetaToken {
 pub enum Token {
     Literal(u8),
     Length(u8), // 0~255 <-> 3~258
-    // let d = distance - 1; d <-> 0~2^15-1
-    Distance0(u8), // 0~255
-    Distance1(u8), // 0~127 <-> 256 x (0~127)
+    // let d = distance - 1; d <-> 0~2^window_bits-1
+    Distance0(u8),  // 0~255
+    Distance1(u16), // 0~2^(window_bits-8)-1 <-> 256 x (0~2^(window_bits-8)-1)
     Meta(MetaToken),
 }
 
-impl From<Token> for u16 {
-    fn from(token: Token) -> Self {
+/// Token ids for a window of 2^window_bits bytes: 256 literals + 256 lengths
+/// + 256 Distance0 + 2^(window_bits-8) Distance1 + 3 meta tokens
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub struct Vocab {
+    window_bits: u32,
+}
+
+impl Default for Vocab {
+    fn default() -> Self {
+        Self {
+            window_bits: DEFAULT_WINDOW_BITS,
+        }
+    }
+}
+
+impl Vocab {
+    pub fn new(window_bits: u32) -> Option<Self> {
+        (MIN_WINDOW_BITS..=MAX_WINDOW_BITS)
+            .contains(&window_bits)
+            .then_some(Self { window_bits })
+    }
+
+    /// the vocabulary with this many ids, if any
+    pub fn from_size(size: usize) -> Option<Self> {
+        (MIN_WINDOW_BITS..=MAX_WINDOW_BITS)
+            .map(|window_bits| Self { window_bits })
+            .find(|vocab| vocab.size() == size)
+    }
+
+    pub fn window_bits(&self) -> u32 {
+        self.window_bits
+    }
+
+    pub fn max_distance(&self) -> usize {
+        1 << self.window_bits
+    }
+
+    fn distance1_len(&self) -> usize {
+        1 << (self.window_bits - 8)
+    }
+
+    pub fn size(&self) -> usize {
+        768 + self.distance1_len() + 3
+    }
+
+    pub fn id(&self, token: Token) -> u16 {
         match token {
             Token::Literal(x) => x as u16,
             Token::Length(l) => 256 + l as u16,
             Token::Distance0(d) => 512 + d as u16,
             Token::Distance1(d) => {
-                debug_assert!(d <= 128);
-                768 + d as u16
+                debug_assert!((d as usize) < self.distance1_len());
+                768 + d
             }
-            Token::Meta(t) => 896 + u16::from(t),
+            Token::Meta(t) => (768 + self.distance1_len()) as u16 + u16::from(t),
         }
     }
+
+    /// None if the id is out of the vocabulary
+    pub fn token(&self, id: u16) -> Option<Token> {
+        let meta = (768 + self.distance1_len()) as u16;
+        match id {
+            0..=255 => Some(Token::Literal(id as u8)),
+            256..=511 => Some(Token::Length((id & 0xFF) as u8)),
+            512..=767 => Some(Token::Distance0((id & 0xFF) as u8)),
+            _ if id < meta => Some(Token::Distance1(id - 768)),
+            _ if id < meta + 3 => Some(Token::Meta(MetaToken::from(id - meta))),
+            _ => None,
+        }
+    }
+}
+
+impl From<Token> for u16 {
+    fn from(token: Token) -> Self {
+        Vocab::default().id(token)
+    }
 }
 
 impl From<u16> for Token {
     fn from(x: u16) -> Self {
-        match x {
-            0..=255 => Token::Literal(x as u8),
-            256..=511 => Token::Length((x & 0xFF) as u8),
-            512..=767 => Token::Distance0((x & 0xFF) as u8),
-            768..=895 => Token::Distance1((x & 0x7F) as u8),
-            896..=898 => Token::Meta(MetaToken::from(x - 896)),
-            _ => unreachable!(),
+        Vocab::default().token(x).unwrap()
+    }
+}
+
+/// (start, end) byte range covered by each token; all three tokens of a match
+/// share its range, and meta tokens cover an empty range at the current offset
+pub fn offsets(tokens: &[Token]) -> Vec<(usize, usize)> {
+    let mut result = Vec::with_capacity(tokens.len());
+    let mut start = 0;
+    let mut end = 0;
+    for &token in tokens {
+        match token {
+            Token::Literal(_) => {
+                start = end;
+                end += 1;
+            }
+            Token::Length(l) => {
+                start = end;
+                end += l as usize + 3;
+            }
+            Token::Distance0(_) | Token::Distance1(_) => {}
+            Token::Meta(_) => start = end,
         }
+        result.push((start, end));
     }
+    result
 }
 
 pub struct EncoderAdaptor<I> {
     iter: I,
     queue: Vec<Token>,
+    count: usize, // number of tokens emitted
+    finished: bool,
+    sentence: bool,
+    pad_to: usize,
+    max_len: usize,
 }
 
 impl<I> EncoderAdaptor<I> {
@@ -76,6 +165,50 @@ impl<I> EncoderAdaptor<I> {
         Self {
             iter,
             queue: Vec::with_capacity(2),
+            count: 0,
+            finished: false,
+            sentence: false,
+            pad_to: 0,
+            max_len: usize::MAX,
+        }
+    }
+
+    pub fn get_mut(&mut self) -> &mut I {
+        &mut self.iter
+    }
+
+    /// begin with StartOfSentence and end with EndOfSentence
+    pub fn with_sentence_tokens(mut self) -> Self {
+        self.sentence = true;
+        self
+    }
+
+    /// append Pad tokens until there are at least len tokens
+    pub fn with_padding(mut self, len: usize) -> Self {
+        self.pad_to = len;
+        self
+    }
+
+    /// stop before the first symbol that does not fit in len tokens, leaving
+    /// room for EndOfSentence, so that the output is decodable on its own
+    pub fn with_truncation(mut self, len: usize) -> Self {
+        self.max_len = len;
+        self
+    }
+
+    fn emit(&mut self, token: Token) -> Option<Token> {
+        self.count += 1;
+        Some(token)
+    }
+
+    /// EndOfSentence if requested, then Pad up to pad_to
+    fn next_meta(&mut self, end: bool) -> Option<Token> {
+        if end {
+            self.emit(Token::Meta(MetaToken::EndOfSentence))
+        } else if self.count < self.pad_to {
+            self.emit(Token::Meta(MetaToken::Pad))
+        } else {
+            None
         }
     }
 }
@@ -85,62 +218,211 @@ impl<I: Iterator<Item = Lz77>> Iterator for EncoderAdaptor<I> {
 
     fn next(&mut self) -> Option<Self::Item> {
         if let Some(token) = self.queue.pop() {
-            return Some(token);
+            return self.emit(token);
+        }
+        if self.sentence && self.count == 0 {
+            return self.emit(Token::Meta(MetaToken::StartOfSentence));
         }
 
-        let token = match self.iter.next()? {
-            Lz77::Literal(x) => Token::Literal(x),
-            Lz77::Dictionary { length, distance } => {
+        let code = if self.finished {
+            None
+        } else {
+            self.iter.next()
+        };
+        let n = match code {
+            Some(Lz77::Literal(_)) => 1,
+            Some(Lz77::Dictionary { .. }) => 3,
+            None => 0,
+        };
+        let code = code.filter(|_| self.count + n + self.sentence as usize <= self.max_len);
+        let token = match code {
+            Some(Lz77::Literal(x)) => Token::Literal(x),
+            Some(Lz77::Dictionary { length, distance }) => {
                 let token = Token::Length(length);
                 // queue distance tokens in reverse order
                 let distance = distance - 1;
-                debug_assert!(distance < 1 << 15);
-                self.queue
-                    .push(Token::Distance1(((distance & 0x7F00) >> 8) as u8));
+                self.queue.push(Token::Distance1((distance >> 8) as u16));
                 self.queue.push(Token::Distance0((distance & 0xFF) as u8));
                 token
             }
+            None if !self.finished => {
+                self.finished = true;
+                return self.next_meta(self.sentence);
+            }
+            None => return self.next_meta(false),
         };
-        Some(token)
+        self.emit(token)
     }
 }
 
+/// validates the token stream, so that the Lz77 codes are safe to decode;
+/// in lenient mode, invalid tokens are repaired instead and logged in repairs().
+/// StartOfSentence is skipped at the beginning, Pad is skipped anywhere between
+/// symbols, and EndOfSentence ends the stream
 pub struct DecoderAdapter<I> {
     iter: I,
+    position: usize, // number of tokens consumed
+    len: usize,      // number of bytes decoded so far
+    dict_len: usize, // length of the preset dictionary, if any
+    ended: bool,
+    pending: Option<(usize, Token)>,
+    lenient: bool,
+    repairs: Vec<Repair>,
 }
 
 impl<I> DecoderAdapter<I> {
     pub fn new(iter: I) -> Self {
-        Self { iter }
+        Self {
+            iter,
+            position: 0,
+            len: 0,
+            dict_len: 0,
+            ended: false,
+            pending: None,
+            lenient: false,
+            repairs: Vec::new(),
+        }
+    }
+
+    pub fn lenient(iter: I) -> Self {
+        Self {
+            lenient: true,
+            ..Self::new(iter)
+        }
+    }
+
+    /// allow back-references into a preset dictionary of this length, see Decoder::with_dictionary
+    pub fn with_dictionary_len(mut self, len: usize) -> Self {
+        self.dict_len = len;
+        self
+    }
+
+    pub fn get_mut(&mut self) -> &mut I {
+        &mut self.iter
+    }
+
+    pub fn repairs(&self) -> &[Repair] {
+        &self.repairs
     }
 }
 
-impl<I: Iterator<Item = Token>> Iterator for DecoderAdapter<I> {
-    type Item = Lz77;
+impl<I: Iterator<Item = Token>> DecoderAdapter<I> {
+    fn next_token(&mut self) -> Option<(usize, Token)> {
+        if let Some(x) = self.pending.take() {
+            return Some(x);
+        }
+        let token = self.iter.next()?;
+        self.position += 1;
+        Some((self.position - 1, token))
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        let code = match self.iter.next()? {
-            Token::Literal(x) => Lz77::Literal(x),
+    /// returns the payload if the next token is of the expected kind, otherwise
+    /// the token is put back in lenient mode
+    fn expect_token(
+        &mut self,
+        f: impl Fn(Token) -> Option<u16>,
+    ) -> Result<Option<u16>, DecodeError> {
+        match self.next_token() {
+            Some((position, token)) => match f(token) {
+                Some(x) => Ok(Some(x)),
+                None if self.lenient => {
+                    self.pending = Some((position, token));
+                    Ok(None)
+                }
+                None => Err(DecodeError::UnexpectedToken { position, token }),
+            },
+            None if self.lenient => Ok(None),
+            None => Err(DecodeError::UnexpectedEnd {
+                position: self.position,
+            }),
+        }
+    }
+
+    fn drop_token(&mut self, position: usize, token: Token) -> Result<Option<Lz77>, DecodeError> {
+        if self.lenient {
+            self.repairs.push(Repair::Dropped { position, token });
+            Ok(None)
+        } else {
+            Err(DecodeError::UnexpectedToken { position, token })
+        }
+    }
+
+    /// Ok(None) if the token is dropped in lenient mode
+    fn decode(&mut self, position: usize, token: Token) -> Result<Option<Lz77>, DecodeError> {
+        match token {
+            Token::Literal(x) => {
+                self.len += 1;
+                Ok(Some(Lz77::Literal(x)))
+            }
             Token::Length(l) => {
-                let d0 = match self.iter.next() {
-                    Some(Token::Distance0(x)) => x,
-                    _ => unreachable!(),
+                let d0 = match self.expect_token(|token| match token {
+                    Token::Distance0(x) => Some(x as u16),
+                    _ => None,
+                })? {
+                    Some(x) => x,
+                    None => return self.drop_token(position, token),
                 };
-                let d1 = match self.iter.next() {
-                    Some(Token::Distance1(x)) => x,
-                    _ => unreachable!(),
+                let d1 = match self.expect_token(|token| match token {
+                    Token::Distance1(x) => Some(x),
+                    _ => None,
+                })? {
+                    Some(x) => x,
+                    None => {
+                        self.repairs.push(Repair::MissingDistance1 { position });
+                        0
+                    }
                 };
-                let distance = (d0 as u16 | (d1 as u16) << 8) + 1;
-                Lz77::Dictionary {
+                let mut distance = (d0 as u32 | (d1 as u32) << 8) + 1;
+                let window = self.dict_len + self.len;
+                if distance as usize > window {
+                    if !self.lenient {
+                        return Err(DecodeError::InvalidDistance {
+                            position,
+                            distance: distance as usize,
+                        });
+                    }
+                    if window == 0 {
+                        return self.drop_token(position, token);
+                    }
+                    self.repairs.push(Repair::ClampedDistance {
+                        position,
+                        distance: distance as usize,
+                    });
+                    distance = window as u32;
+                }
+                self.len += l as usize + 3;
+                Ok(Some(Lz77::Dictionary {
                     length: l,
                     distance,
-                }
+                }))
             }
-            Token::Distance0(_) => unreachable!(),
-            Token::Distance1(_) => unreachable!(),
-            Token::Meta(_) => unreachable!(),
-        };
+            Token::Meta(MetaToken::StartOfSentence) if self.len == 0 => Ok(None),
+            Token::Meta(MetaToken::Pad) => Ok(None),
+            Token::Meta(MetaToken::EndOfSentence) => {
+                self.ended = true;
+                Ok(None)
+            }
+            Token::Distance0(_) | Token::Distance1(_) | Token::Meta(_) => {
+                self.drop_token(position, token)
+            }
+        }
+    }
+}
 
-        Some(code)
+impl<I: Iterator<Item = Token>> Iterator for DecoderAdapter<I> {
+    type Item = Result<Lz77, DecodeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.ended {
+                return None;
+            }
+            let (position, token) = self.next_token()?;
+            match self.decode(position, token) {
+                Ok(Some(code)) => return Some(Ok(code)),
+                Ok(None) => {}
+                Err(e) => return Some(Err(e)),
+            }
+        }
     }
 }
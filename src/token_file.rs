@@ -0,0 +1,284 @@
+use std::io::{BufRead, Error, ErrorKind, Result, Write};
+
+use serde_json::{json, Value};
+
+use crate::token::Vocab;
+
+pub const MAGIC: [u8; 4] = *b"LZTK";
+pub const VERSION: u16 = 1;
+const FLAG_DOCUMENTS: u16 = 1;
+const HEADER_LEN: u64 = 28;
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum TokenFormat {
+    /// one decimal id per line; if the boundaries are recorded, each document ends
+    /// with an empty line, which files that only separate documents by one omit after
+    /// the last, so reading those drops a trailing empty document
+    Text,
+    /// header followed by little-endian u16 ids, zeros up to a multiple of 8 bytes,
+    /// and u64 document end offsets: magic (4 bytes), version (u16), flags (u16),
+    /// vocabulary size (u32), number of tokens (u64), number of documents (u64)
+    Bin,
+    /// {"version": .., "vocab_size": .., "tokens": [..], "documents": [..]}
+    Json,
+}
+
+impl TokenFormat {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "text" => Some(Self::Text),
+            "bin" => Some(Self::Bin),
+            "json" => Some(Self::Json),
+            _ => None,
+        }
+    }
+}
+
+/// Token ids of one or more documents along with the vocabulary they belong to,
+/// identified by its size, which tells the window, see Vocab::from_size
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct TokenFile {
+    pub vocab_size: u32,
+    pub tokens: Vec<u16>,
+    /// end offset of each document in tokens, if the boundaries are recorded
+    pub documents: Option<Vec<u64>>,
+}
+
+impl TokenFile {
+    pub fn new(tokens: Vec<u16>) -> Self {
+        Self {
+            vocab_size: Vocab::default().size() as u32,
+            tokens,
+            documents: None,
+        }
+    }
+
+    /// for ids of a vocabulary other than the default one
+    pub fn with_vocab(mut self, vocab: Vocab) -> Self {
+        self.vocab_size = vocab.size() as u32;
+        self
+    }
+
+    pub fn vocab(&self) -> Option<Vocab> {
+        Vocab::from_size(self.vocab_size as usize)
+    }
+
+    pub fn from_documents(documents: &[Vec<u16>]) -> Self {
+        let mut tokens = Vec::new();
+        let mut ends = Vec::with_capacity(documents.len());
+        for document in documents {
+            tokens.extend_from_slice(document);
+            ends.push(tokens.len() as u64);
+        }
+        Self {
+            documents: Some(ends),
+            ..Self::new(tokens)
+        }
+    }
+
+    /// tokens of each document; all tokens as one document without recorded boundaries
+    pub fn documents(&self) -> Vec<&[u16]> {
+        match &self.documents {
+            Some(ends) => {
+                let mut start = 0;
+                ends.iter()
+                    .map(|&end| {
+                        let document = &self.tokens[start..end as usize];
+                        start = end as usize;
+                        document
+                    })
+                    .collect()
+            }
+            None => vec![&self.tokens],
+        }
+    }
+
+    pub fn write<W: Write>(&self, mut writer: W, format: TokenFormat) -> Result<()> {
+        match format {
+            TokenFormat::Text => {
+                for document in self.documents() {
+                    for x in document.iter() {
+                        writeln!(writer, "{}", x)?;
+                    }
+                    if self.documents.is_some() {
+                        writer.write_all(b"\n")?;
+                    }
+                }
+            }
+            TokenFormat::Bin => {
+                let flags = if self.documents.is_some() {
+                    FLAG_DOCUMENTS
+                } else {
+                    0
+                };
+                let ends = self.documents.as_deref().unwrap_or_default();
+                writer.write_all(&MAGIC)?;
+                writer.write_all(&VERSION.to_le_bytes())?;
+                writer.write_all(&flags.to_le_bytes())?;
+                writer.write_all(&self.vocab_size.to_le_bytes())?;
+                writer.write_all(&(self.tokens.len() as u64).to_le_bytes())?;
+                writer.write_all(&(ends.len() as u64).to_le_bytes())?;
+                for x in &self.tokens {
+                    writer.write_all(&x.to_le_bytes())?;
+                }
+                let n = self.tokens.len() as u64;
+                let padding = ends_offset(n).expect("file length") - HEADER_LEN - 2 * n;
+                writer.write_all(&vec![0; padding as usize])?;
+                for end in ends {
+                    writer.write_all(&end.to_le_bytes())?;
+                }
+            }
+            TokenFormat::Json => {
+                let mut value = json!({
+                    "version": VERSION,
+                    "vocab_size": self.vocab_size,
+                    "tokens": self.tokens,
+                });
+                if let Some(ends) = &self.documents {
+                    value["documents"] = json!(ends);
+                }
+                serde_json::to_writer(&mut writer, &value)?;
+                writer.write_all(b"\n")?;
+            }
+        }
+        writer.flush()
+    }
+
+    /// parse and validate against the vocabulary in the header; text records none,
+    /// so it is taken to be vocab
+    pub fn read<R: BufRead>(mut reader: R, format: TokenFormat, vocab: Vocab) -> Result<Self> {
+        let file = match format {
+            TokenFormat::Text => {
+                let mut tokens = Vec::new();
+                let mut ends = Vec::new();
+                for line in reader.lines() {
+                    let line = line?;
+                    let line = line.trim();
+                    if line.is_empty() {
+                        ends.push(tokens.len() as u64);
+                        continue;
+                    }
+                    let x = line
+                        .parse()
+                        .map_err(|_| invalid(format!("invalid token: {}", line)))?;
+                    tokens.push(x);
+                }
+                let mut file = Self::new(tokens).with_vocab(vocab);
+                if !ends.is_empty() {
+                    // the last document is unterminated if only separated
+                    if ends.last() != Some(&(file.tokens.len() as u64)) {
+                        ends.push(file.tokens.len() as u64);
+                    }
+                    file.documents = Some(ends);
+                }
+                file
+            }
+            TokenFormat::Bin => {
+                let mut header = [0; HEADER_LEN as usize];
+                reader.read_exact(&mut header)?;
+                if header[..4] != MAGIC {
+                    return Err(invalid("invalid magic number".to_string()));
+                }
+                let version = u16::from_le_bytes([header[4], header[5]]);
+                if version != VERSION {
+                    return Err(invalid(format!("unsupported version {}", version)));
+                }
+                let flags = u16::from_le_bytes([header[6], header[7]]);
+                let vocab_size = u32::from_le_bytes(header[8..12].try_into().unwrap());
+                let n = u64::from_le_bytes(header[12..20].try_into().unwrap());
+                let m = u64::from_le_bytes(header[20..28].try_into().unwrap());
+                let mut bytes = Vec::new();
+                reader.read_to_end(&mut bytes)?;
+                let offset = ends_offset(n);
+                let len = offset
+                    .zip(m.checked_mul(8))
+                    .and_then(|(x, y)| x.checked_add(y));
+                if len != Some(HEADER_LEN + bytes.len() as u64) {
+                    return Err(invalid("invalid file length".to_string()));
+                }
+                let tokens = bytes[..2 * n as usize]
+                    .chunks_exact(2)
+                    .map(|x| u16::from_le_bytes([x[0], x[1]]))
+                    .collect();
+                let ends = bytes[(offset.unwrap() - HEADER_LEN) as usize..]
+                    .chunks_exact(8)
+                    .map(|x| u64::from_le_bytes(x.try_into().unwrap()))
+                    .collect();
+                Self {
+                    vocab_size,
+                    tokens,
+                    documents: (flags & FLAG_DOCUMENTS != 0).then_some(ends),
+                }
+            }
+            TokenFormat::Json => {
+                let value: Value = serde_json::from_reader(reader)?;
+                let version = value["version"].as_u64();
+                if version != Some(VERSION as u64) {
+                    return Err(invalid(format!("unsupported version {:?}", version)));
+                }
+                let vocab_size = value["vocab_size"]
+                    .as_u64()
+                    .ok_or_else(|| invalid("missing vocab_size".to_string()))?;
+                let array = |key: &str| -> Result<Option<Vec<u64>>> {
+                    match &value[key] {
+                        Value::Null => Ok(None),
+                        Value::Array(xs) => xs
+                            .iter()
+                            .map(|x| {
+                                x.as_u64()
+                                    .ok_or_else(|| invalid(format!("invalid {}", key)))
+                            })
+                            .collect::<Result<_>>()
+                            .map(Some),
+                        _ => Err(invalid(format!("invalid {}", key))),
+                    }
+                };
+                let tokens =
+                    array("tokens")?.ok_or_else(|| invalid("missing tokens".to_string()))?;
+                Self {
+                    vocab_size: vocab_size as u32,
+                    tokens: tokens
+                        .into_iter()
+                        .map(|x| {
+                            u16::try_from(x).map_err(|_| invalid(format!("invalid token: {}", x)))
+                        })
+                        .collect::<Result<_>>()?,
+                    documents: array("documents")?,
+                }
+            }
+        };
+        file.validate()?;
+        Ok(file)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.vocab().is_none() {
+            return Err(invalid(format!(
+                "unknown vocabulary size {}",
+                self.vocab_size
+            )));
+        }
+        if let Some(x) = self.tokens.iter().find(|&&x| x >= self.vocab_size as u16) {
+            return Err(invalid(format!("invalid token: {}", x)));
+        }
+        if let Some(ends) = &self.documents {
+            let sorted = ends.windows(2).all(|w| w[0] <= w[1]);
+            if !sorted || ends.last().copied().unwrap_or(0) != self.tokens.len() as u64 {
+                return Err(invalid("invalid document boundaries".to_string()));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// offset of the document ends in a Bin file of n tokens, aligned to 8 bytes so that
+/// they can be memory-mapped as u64
+fn ends_offset(n: u64) -> Option<u64> {
+    n.checked_mul(2)?
+        .checked_add(HEADER_LEN + 7)
+        .map(|x| x & !7)
+}
+
+fn invalid(message: String) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
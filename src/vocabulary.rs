@@ -0,0 +1,455 @@
+use crate::decoder::DecodeError;
+use crate::deflate::{length_code, LENGTH_BASE, LENGTH_EXTRA};
+use crate::lz77::{Lz77, DEFAULT_WINDOW_BITS, MAX_WINDOW_BITS, MIN_WINDOW_BITS};
+use crate::token::{MetaToken, Token, Vocab};
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum Symbol {
+    Code(Lz77),
+    Meta(MetaToken),
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum ParseError {
+    /// the id at this offset is outside of the vocabulary or not allowed there
+    Unexpected(usize),
+    /// ids end in the middle of a symbol
+    End,
+}
+
+/// A layout of token ids for the Lz77 codes, so that the same parse can be tokenized
+/// in different ways; a match may take any number of ids, and the 3 meta tokens
+/// come last
+pub trait Vocabulary {
+    fn vocab_size(&self) -> usize;
+
+    /// the largest distance is 2^window_bits
+    fn window_bits(&self) -> u32;
+
+    fn meta_id(&self, token: MetaToken) -> u16 {
+        (self.vocab_size() - 3) as u16 + u16::from(token)
+    }
+
+    /// appends the ids of a code
+    fn push_ids(&self, code: Lz77, ids: &mut Vec<u16>);
+
+    /// the symbol that ids start with and the number of ids it takes
+    fn parse(&self, ids: &[u16]) -> Result<(Symbol, usize), ParseError>;
+}
+
+/// Choice of vocabulary, e.g., from the command line or Python
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum Layout {
+    /// Vocab: length byte, then the distance as a low byte and a high part
+    Bytes,
+    /// DeflateVocab: DEFLATE length/distance codes followed by their extra bits
+    Deflate,
+    /// JointVocab: length byte, then one token per distance
+    Joint,
+    /// LogVocab: length byte, then log2 bucket of the distance and its residual
+    Log,
+}
+
+impl Layout {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "bytes" => Some(Self::Bytes),
+            "deflate" => Some(Self::Deflate),
+            "joint" => Some(Self::Joint),
+            "log" => Some(Self::Log),
+            _ => None,
+        }
+    }
+
+    /// None if the layout does not support the window
+    pub fn vocabulary(self, window_bits: u32) -> Option<Box<dyn Vocabulary + Send + Sync>> {
+        fn boxed<V: Vocabulary + Send + Sync + 'static>(
+            vocab: V,
+        ) -> Box<dyn Vocabulary + Send + Sync> {
+            Box::new(vocab)
+        }
+        match self {
+            Self::Bytes => Vocab::new(window_bits).map(boxed),
+            Self::Deflate => DeflateVocab::new(window_bits).map(boxed),
+            Self::Joint => JointVocab::new(window_bits).map(boxed),
+            Self::Log => LogVocab::new(window_bits).map(boxed),
+        }
+    }
+}
+
+fn get(ids: &[u16], offset: usize) -> Result<u16, ParseError> {
+    ids.get(offset).copied().ok_or(ParseError::End)
+}
+
+/// id - start if it is within start..start + len
+fn get_in(ids: &[u16], offset: usize, start: usize, len: usize) -> Result<u32, ParseError> {
+    let id = get(ids, offset)? as usize;
+    if (start..start + len).contains(&id) {
+        Ok((id - start) as u32)
+    } else {
+        Err(ParseError::Unexpected(offset))
+    }
+}
+
+/// a value of bits bits as byte tokens, low byte first unless msb_first
+fn push_bytes(value: u32, bits: u32, start: usize, msb_first: bool, ids: &mut Vec<u16>) {
+    let n = bits.div_ceil(8);
+    for i in 0..n {
+        let shift = if msb_first { 8 * (n - 1 - i) } else { 8 * i };
+        ids.push((start as u32 + (value >> shift & 0xFF)) as u16);
+    }
+}
+
+/// inverse of push_bytes, rejecting bytes with bits above the value's;
+/// returns the value and the number of ids
+fn parse_bytes(
+    ids: &[u16],
+    offset: usize,
+    bits: u32,
+    start: usize,
+    msb_first: bool,
+) -> Result<(u32, usize), ParseError> {
+    let n = bits.div_ceil(8);
+    let mut value = 0;
+    for i in 0..n {
+        let shift = if msb_first { 8 * (n - 1 - i) } else { 8 * i };
+        let x = get_in(ids, offset + i as usize, start, 1 << (bits - shift).min(8))?;
+        value |= x << shift;
+    }
+    Ok((value, n as usize))
+}
+
+fn parse_meta(vocab: &impl Vocabulary, id: u16) -> Result<(Symbol, usize), ParseError> {
+    let meta = vocab.vocab_size() - 3;
+    match (id as usize).checked_sub(meta) {
+        Some(x) if x < 3 => Ok((Symbol::Meta(MetaToken::from(x as u16)), 1)),
+        _ => Err(ParseError::Unexpected(0)),
+    }
+}
+
+fn window_bits_in(window_bits: u32, max: u32) -> bool {
+    (MIN_WINDOW_BITS..=max).contains(&window_bits)
+}
+
+impl Vocabulary for Vocab {
+    fn vocab_size(&self) -> usize {
+        self.size()
+    }
+
+    fn window_bits(&self) -> u32 {
+        Vocab::window_bits(self)
+    }
+
+    fn push_ids(&self, code: Lz77, ids: &mut Vec<u16>) {
+        match code {
+            Lz77::Literal(x) => ids.push(self.id(Token::Literal(x))),
+            Lz77::Dictionary { length, distance } => {
+                let distance = distance - 1;
+                ids.push(self.id(Token::Length(length)));
+                ids.push(self.id(Token::Distance0((distance & 0xFF) as u8)));
+                ids.push(self.id(Token::Distance1((distance >> 8) as u16)));
+            }
+        }
+    }
+
+    fn parse(&self, ids: &[u16]) -> Result<(Symbol, usize), ParseError> {
+        let token = |offset| {
+            self.token(get(ids, offset)?)
+                .ok_or(ParseError::Unexpected(offset))
+        };
+        match token(0)? {
+            Token::Literal(x) => Ok((Symbol::Code(Lz77::Literal(x)), 1)),
+            Token::Length(length) => {
+                let Token::Distance0(d0) = token(1)? else {
+                    return Err(ParseError::Unexpected(1));
+                };
+                let Token::Distance1(d1) = token(2)? else {
+                    return Err(ParseError::Unexpected(2));
+                };
+                let distance = (d0 as u32 | (d1 as u32) << 8) + 1;
+                Ok((Symbol::Code(Lz77::Dictionary { length, distance }), 3))
+            }
+            Token::Meta(token) => Ok((Symbol::Meta(token), 1)),
+            Token::Distance0(_) | Token::Distance1(_) => Err(ParseError::Unexpected(0)),
+        }
+    }
+}
+
+/// (code, extra bits value, number of extra bits) of a distance, with DEFLATE's
+/// distance codes extended by two codes per doubling of the window as in Deflate64
+fn dist_code(distance: u32) -> (u32, u32, u32) {
+    let d = distance - 1;
+    if d < 4 {
+        return (d, 0, 0);
+    }
+    let log2 = 31 - d.leading_zeros();
+    let extra = log2 - 1;
+    (2 * log2 + (d >> extra & 1), d & ((1 << extra) - 1), extra)
+}
+
+/// (distance - 1 of the code without its extra bits, number of extra bits)
+fn dist_base(code: u32) -> (u32, u32) {
+    if code < 4 {
+        return (code, 0);
+    }
+    let extra = code / 2 - 1;
+    ((2 | (code & 1)) << extra, extra)
+}
+
+/// Ids as in DEFLATE: 256 literals, 29 length codes, 2 x window_bits distance codes,
+/// 256 extra bits tokens and 3 meta tokens. A match is its length code, the length
+/// extra bits if any, the distance code, then the distance extra bits, a token for
+/// each 8 bits, low byte first
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub struct DeflateVocab {
+    window_bits: u32,
+}
+
+impl Default for DeflateVocab {
+    fn default() -> Self {
+        Self {
+            window_bits: DEFAULT_WINDOW_BITS,
+        }
+    }
+}
+
+impl DeflateVocab {
+    const LENGTH: usize = 256;
+    const DISTANCE: usize = Self::LENGTH + LENGTH_BASE.len();
+
+    pub fn new(window_bits: u32) -> Option<Self> {
+        window_bits_in(window_bits, MAX_WINDOW_BITS).then_some(Self { window_bits })
+    }
+
+    fn extra(&self) -> usize {
+        Self::DISTANCE + 2 * self.window_bits as usize
+    }
+}
+
+impl Vocabulary for DeflateVocab {
+    fn vocab_size(&self) -> usize {
+        self.extra() + 256 + 3
+    }
+
+    fn window_bits(&self) -> u32 {
+        self.window_bits
+    }
+
+    fn push_ids(&self, code: Lz77, ids: &mut Vec<u16>) {
+        match code {
+            Lz77::Literal(x) => ids.push(x as u16),
+            Lz77::Dictionary { length, distance } => {
+                let (i, extra) = length_code(length as usize + 3);
+                ids.push((Self::LENGTH + i) as u16);
+                push_bytes(extra, LENGTH_EXTRA[i] as u32, self.extra(), false, ids);
+                let (code, extra, bits) = dist_code(distance);
+                ids.push((Self::DISTANCE + code as usize) as u16);
+                push_bytes(extra, bits, self.extra(), false, ids);
+            }
+        }
+    }
+
+    fn parse(&self, ids: &[u16]) -> Result<(Symbol, usize), ParseError> {
+        let id = get(ids, 0)?;
+        if id < 256 {
+            return Ok((Symbol::Code(Lz77::Literal(id as u8)), 1));
+        }
+        let Ok(i) = get_in(ids, 0, Self::LENGTH, LENGTH_BASE.len()) else {
+            return parse_meta(self, id);
+        };
+        let i = i as usize;
+        let (extra, n) = parse_bytes(ids, 1, LENGTH_EXTRA[i] as u32, self.extra(), false)?;
+        let length = LENGTH_BASE[i] as u32 + extra;
+        let code = get_in(ids, 1 + n, Self::DISTANCE, 2 * self.window_bits as usize)?;
+        let (base, bits) = dist_base(code);
+        let (extra, m) = parse_bytes(ids, 2 + n, bits, self.extra(), false)?;
+        let code = Lz77::Dictionary {
+            length: (length - 3) as u8,
+            distance: base + extra + 1,
+        };
+        Ok((Symbol::Code(code), 2 + n + m))
+    }
+}
+
+/// Ids for small windows: 256 literals, 256 lengths, 2^window_bits distances and
+/// 3 meta tokens, so that a match is always two tokens; up to 32k to fit in u16
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub struct JointVocab {
+    window_bits: u32,
+}
+
+impl JointVocab {
+    pub const MAX_WINDOW_BITS: u32 = 15;
+
+    pub fn new(window_bits: u32) -> Option<Self> {
+        window_bits_in(window_bits, Self::MAX_WINDOW_BITS).then_some(Self { window_bits })
+    }
+}
+
+impl Vocabulary for JointVocab {
+    fn vocab_size(&self) -> usize {
+        512 + (1 << self.window_bits) + 3
+    }
+
+    fn window_bits(&self) -> u32 {
+        self.window_bits
+    }
+
+    fn push_ids(&self, code: Lz77, ids: &mut Vec<u16>) {
+        match code {
+            Lz77::Literal(x) => ids.push(x as u16),
+            Lz77::Dictionary { length, distance } => {
+                ids.push(256 + length as u16);
+                ids.push((512 + distance - 1) as u16);
+            }
+        }
+    }
+
+    fn parse(&self, ids: &[u16]) -> Result<(Symbol, usize), ParseError> {
+        let id = get(ids, 0)?;
+        match id {
+            0..=255 => Ok((Symbol::Code(Lz77::Literal(id as u8)), 1)),
+            256..=511 => {
+                let distance = get_in(ids, 1, 512, 1 << self.window_bits)? + 1;
+                let length = (id - 256) as u8;
+                Ok((Symbol::Code(Lz77::Dictionary { length, distance }), 2))
+            }
+            _ => parse_meta(self, id),
+        }
+    }
+}
+
+/// Ids with log-bucketed distances: 256 literals, 256 lengths, window_bits + 1
+/// buckets, 256 residual tokens and 3 meta tokens. A match is its length, the
+/// bucket of distance - 1, i.e., its bit length, then the bits below the leading
+/// one, a token for each 8 bits, most significant first so that the coarse
+/// position comes before the fine one
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub struct LogVocab {
+    window_bits: u32,
+}
+
+impl Default for LogVocab {
+    fn default() -> Self {
+        Self {
+            window_bits: DEFAULT_WINDOW_BITS,
+        }
+    }
+}
+
+impl LogVocab {
+    const BUCKET: usize = 512;
+
+    pub fn new(window_bits: u32) -> Option<Self> {
+        window_bits_in(window_bits, MAX_WINDOW_BITS).then_some(Self { window_bits })
+    }
+
+    fn residual(&self) -> usize {
+        Self::BUCKET + self.window_bits as usize + 1
+    }
+}
+
+impl Vocabulary for LogVocab {
+    fn vocab_size(&self) -> usize {
+        self.residual() + 256 + 3
+    }
+
+    fn window_bits(&self) -> u32 {
+        self.window_bits
+    }
+
+    fn push_ids(&self, code: Lz77, ids: &mut Vec<u16>) {
+        match code {
+            Lz77::Literal(x) => ids.push(x as u16),
+            Lz77::Dictionary { length, distance } => {
+                ids.push(256 + length as u16);
+                let d = distance - 1;
+                let bucket = 32 - d.leading_zeros();
+                ids.push((Self::BUCKET + bucket as usize) as u16);
+                if bucket > 1 {
+                    let bits = bucket - 1;
+                    push_bytes(d & ((1 << bits) - 1), bits, self.residual(), true, ids);
+                }
+            }
+        }
+    }
+
+    fn parse(&self, ids: &[u16]) -> Result<(Symbol, usize), ParseError> {
+        let id = get(ids, 0)?;
+        match id {
+            0..=255 => Ok((Symbol::Code(Lz77::Literal(id as u8)), 1)),
+            256..=511 => {
+                let bucket = get_in(ids, 1, Self::BUCKET, self.window_bits as usize + 1)?;
+                let (d, n) = match bucket {
+                    0 => (0, 0),
+                    _ => {
+                        let bits = bucket - 1;
+                        let (residual, n) = parse_bytes(ids, 2, bits, self.residual(), true)?;
+                        (1 << bits | residual, n)
+                    }
+                };
+                let code = Lz77::Dictionary {
+                    length: (id - 256) as u8,
+                    distance: d + 1,
+                };
+                Ok((Symbol::Code(code), 2 + n))
+            }
+            _ => parse_meta(self, id),
+        }
+    }
+}
+
+/// Lz77 codes of the ids, validated as DecoderAdapter does: StartOfSentence is
+/// skipped at the beginning, Pad is skipped anywhere between symbols, and
+/// EndOfSentence ends the stream
+pub fn parse_codes<V: Vocabulary + ?Sized>(
+    vocab: &V,
+    ids: &[u16],
+    dict_len: usize,
+) -> Result<Vec<Lz77>, DecodeError> {
+    let mut codes = Vec::new();
+    let mut position = 0;
+    let mut len = 0;
+    while position < ids.len() {
+        let (symbol, n) = vocab.parse(&ids[position..]).map_err(|e| match e {
+            ParseError::Unexpected(offset) => {
+                let (position, id) = (position + offset, ids[position + offset]);
+                if id as usize >= vocab.vocab_size() {
+                    DecodeError::InvalidToken { position, id }
+                } else {
+                    DecodeError::UnexpectedId { position, id }
+                }
+            }
+            ParseError::End => DecodeError::UnexpectedEnd {
+                position: ids.len(),
+            },
+        })?;
+        match symbol {
+            Symbol::Code(code) => {
+                if let Lz77::Dictionary { length, distance } = code {
+                    if distance as usize > dict_len + len {
+                        return Err(DecodeError::InvalidDistance {
+                            position,
+                            distance: distance as usize,
+                        });
+                    }
+                    len += length as usize + 3;
+                } else {
+                    len += 1;
+                }
+                codes.push(code);
+            }
+            Symbol::Meta(MetaToken::StartOfSentence) if len == 0 => {}
+            Symbol::Meta(MetaToken::Pad) => {}
+            Symbol::Meta(MetaToken::EndOfSentence) => break,
+            Symbol::Meta(MetaToken::StartOfSentence) => {
+                return Err(DecodeError::UnexpectedId {
+                    position,
+                    id: ids[position],
+                })
+            }
+        }
+        position += n;
+    }
+    Ok(codes)
+}
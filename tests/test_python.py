@@ -0,0 +1,89 @@
+# Tests of the Python bindings; run with `maturin develop && pytest tests`
+import array
+
+import numpy as np
+import pytest
+
+import lz
+
+TEXT = "abcabcabcabc hello, world! " * 20
+
+
+def test_input_types():
+    expected = lz.encode(TEXT.encode())
+    inputs = [
+        TEXT,
+        TEXT.encode(),
+        bytearray(TEXT.encode()),
+        memoryview(TEXT.encode()),
+        memoryview(TEXT.encode())[::1],
+        array.array("B", TEXT.encode()),
+        np.frombuffer(TEXT.encode(), dtype=np.uint8),
+        # writeable, copied
+        np.frombuffer(TEXT.encode(), dtype=np.uint8).copy(),
+        np.frombuffer(bytearray(TEXT.encode()), dtype=np.uint8),
+        # non-contiguous, copied
+        np.frombuffer((TEXT * 2).encode(), dtype=np.uint8).reshape(2, -1)[0],
+        np.repeat(np.frombuffer(TEXT.encode(), dtype=np.uint8), 2)[::2],
+    ]
+    for xs in inputs:
+        np.testing.assert_array_equal(lz.encode(xs), expected)
+    assert lz.compress(TEXT) == lz.compress(TEXT.encode())
+    np.testing.assert_array_equal(
+        lz.encode(TEXT, dictionary=memoryview(b"hello, world!")),
+        lz.encode(TEXT.encode(), dictionary=b"hello, world!"),
+    )
+    tokens, _ = lz.encode_batch([TEXT, bytearray(b"abc"), memoryview(b"")])
+    assert lz.decode(tokens[1], output="bytes") == b"abc"
+
+
+def test_non_ascii_str():
+    xs = "größe 大小 " * 10
+    assert lz.decode(lz.encode(xs), output="str") == xs
+
+
+def test_output_modes():
+    tokens = lz.encode(TEXT)
+    ys = lz.decode(tokens)
+    assert isinstance(ys, np.ndarray) and ys.dtype == np.uint8
+    assert ys.tobytes() == TEXT.encode()
+    assert lz.decode(tokens, output="numpy").tobytes() == TEXT.encode()
+    assert lz.decode(tokens, output="bytes") == TEXT.encode()
+    assert lz.decode(tokens, output="str") == TEXT
+    with pytest.raises(ValueError):
+        lz.decode(tokens, output="list")
+    # not UTF-8
+    with pytest.raises(ValueError):
+        lz.decode(lz.encode(b"\xff\xfe"), output="str")
+    rows = lz.decode_batch(lz.encode_batch([TEXT, "abc"])[0], output="str")
+    assert rows == [TEXT, "abc"]
+
+
+def test_match_finder():
+    for match_finder in ["hc", "bt"]:
+        tokens = lz.encode(TEXT, level=9, match_finder=match_finder)
+        assert lz.decode(tokens, output="str") == TEXT
+        tokens = lz.encode_parallel(TEXT, level=9, match_finder=match_finder)
+        assert lz.decode(tokens, output="str") == TEXT
+    with pytest.raises(ValueError):
+        lz.encode(TEXT, match_finder="bt4")
+
+
+def test_encode_parallel_block_size():
+    xs = TEXT * 100
+    tokens = lz.encode_parallel(xs, block_size=256, window_bits=8)
+    assert lz.decode(tokens, window_bits=8, output="str") == xs
+    with pytest.raises(ValueError):
+        lz.encode_parallel(xs, block_size=1000)
+
+
+def test_batch_window_bits():
+    tokens, _ = lz.encode_batch([TEXT, "abc"], window_bits=10)
+    rows = lz.decode_batch(tokens, output="str", window_bits=10)
+    assert rows == [TEXT, "abc"]
+
+
+def test_decode_lenient_window_bits():
+    tokens = lz.encode(TEXT, window_bits=10)
+    decoded, repairs = lz.decode_lenient(tokens, output="str", window_bits=10)
+    assert decoded == TEXT and repairs == []